@@ -0,0 +1,72 @@
+// cargo bench --no-default-features --bench apply
+// times one step at each size with the serial path from before rules ran in chunks, and
+// with the chunked path on a single thread and on all threads. checks both chunked runs
+// give the same texture, the serial path draws its random numbers in another order
+use std::time::{Duration, Instant};
+
+use auto::seed::{stream_rng, Stream};
+use auto::sprite_gen::{apply_rules_serial, SpriteGen};
+
+const SEED: u64 = 1;
+const SIZES: [usize; 3] = [256, 1024, 4096];
+
+fn time_steps(sprite_gen: &mut SpriteGen, steps: usize) -> Duration {
+    let start = Instant::now();
+    for _ in 0..steps {
+        sprite_gen.apply();
+    }
+    start.elapsed() / steps as u32
+}
+
+fn time_serial_steps(sprite_gen: &mut SpriteGen, steps: usize) -> Duration {
+    let mut rng = stream_rng(SEED, Stream::Apply);
+    let start = Instant::now();
+    for _ in 0..steps {
+        apply_rules_serial(
+            &mut rng,
+            &mut sprite_gen.char_texture,
+            &sprite_gen.rules,
+            sprite_gen.apply_mode,
+        );
+    }
+    start.elapsed() / steps as u32
+}
+
+fn main() {
+    let single = rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .build()
+        .unwrap();
+    println!("seed {}, {} threads", SEED, rayon::current_num_threads());
+    for size in SIZES {
+        // fewer steps on the big textures, one 4096x4096 step already takes a while
+        let steps = (1 << 22) / (size * size) + 1;
+
+        let mut previous = SpriteGen::new(size, size);
+        previous.randomize_with_seed(SEED);
+        let mut serial = SpriteGen::new(size, size);
+        serial.randomize_with_seed(SEED);
+        let mut parallel = SpriteGen::new(size, size);
+        parallel.randomize_with_seed(SEED);
+
+        let previous_time = time_serial_steps(&mut previous, steps);
+        let serial_time = single.install(|| time_steps(&mut serial, steps));
+        let parallel_time = time_steps(&mut parallel, steps);
+
+        assert!(
+            serial.char_texture.pixels == parallel.char_texture.pixels,
+            "thread count changed the result at {0}x{0}",
+            size
+        );
+        println!(
+            "{0}x{0}, {1} rules: previous {2:.1?}/step, 1 thread {3:.1?}/step, \
+             all threads {4:.1?}/step ({5:.1}x)",
+            size,
+            serial.rules.len(),
+            previous_time,
+            serial_time,
+            parallel_time,
+            previous_time.as_secs_f64() / parallel_time.as_secs_f64()
+        );
+    }
+}
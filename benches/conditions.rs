@@ -0,0 +1,63 @@
+// cargo bench --no-default-features --bench conditions
+// matches every cell against the conditions of a generated rule set, once through the
+// regex on the stringified texture and once through the compiled conditions
+use std::time::{Duration, Instant};
+
+use auto::sprite_gen::SpriteGen;
+
+const SEEDS: [u64; 4] = [1, 2, 3, 4];
+const SIZE: usize = 1024;
+
+fn main() {
+    for seed in SEEDS {
+        let mut sprite_gen = SpriteGen::new(SIZE, SIZE);
+        sprite_gen.randomize_with_seed(seed);
+        let texture = &sprite_gen.char_texture;
+
+        let mut regex_time = Duration::ZERO;
+        let mut compiled_time = Duration::ZERO;
+        let mut compiled_rules = 0;
+        for rule in &sprite_gen.rules {
+            let (condition, compiled) = match (rule.condition(), rule.compiled()) {
+                (Some(condition), Some(compiled)) => (condition, compiled),
+                _ => continue,
+            };
+            compiled_rules += 1;
+            let offsets = rule.offsets();
+            let size = offsets.len();
+
+            let start = Instant::now();
+            let input = texture.full_stringify_offsets(offsets);
+            let regex_matches = (0..texture.pixels.len())
+                .filter(|index| condition.is_match(&input[index * size..(index + 1) * size]))
+                .count();
+            regex_time += start.elapsed();
+
+            let start = Instant::now();
+            let compiled_matches = (0..texture.pixels.len())
+                .filter(|index| {
+                    let (x, y) = texture.xy_from_index(*index);
+                    compiled.matches(texture, x, y, offsets)
+                })
+                .count();
+            compiled_time += start.elapsed();
+
+            assert_eq!(
+                regex_matches,
+                compiled_matches,
+                "{}",
+                rule.original_condition()
+            );
+        }
+        println!(
+            "seed {0}, {1}x{1}, {2}/{3} rules compiled: regex {4:.1?}, compiled {5:.1?} ({6:.1}x)",
+            seed,
+            SIZE,
+            compiled_rules,
+            sprite_gen.rules.len(),
+            regex_time,
+            compiled_time,
+            regex_time.as_secs_f64() / compiled_time.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,493 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, RgbaImage};
+
+use crate::char_texture::CharTexture;
+use crate::palette::luminance;
+use crate::sprite_gen::SpriteGen;
+use crate::texture_noise::noise_fill;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationFormat {
+    Gif,
+    Apng,
+}
+
+impl AnimationFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::Apng => "png",
+        }
+    }
+
+    pub fn mime(&self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "image/gif",
+            AnimationFormat::Apng => "image/apng",
+        }
+    }
+}
+
+/*
+colors the frames are drawn with
+Letters: the letter colors as they are
+Reduced: the closest letter colors are merged until at most this many are left, for
+smaller files
+Grayscale: the luminance of each letter color
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationPalette {
+    Letters,
+    Reduced(usize),
+    Grayscale,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimationSettings {
+    pub format: AnimationFormat,
+    // a frame every this many steps
+    pub every: usize,
+    pub steps: usize,
+    pub frame_delay_ms: u16,
+    pub palette: AnimationPalette,
+    // start from a new noise fill instead of the current letters
+    pub from_noise: bool,
+}
+
+impl Default for AnimationSettings {
+    fn default() -> Self {
+        Self {
+            format: AnimationFormat::Gif,
+            every: 1,
+            steps: 100,
+            frame_delay_ms: 50,
+            palette: AnimationPalette::Letters,
+            from_noise: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum AnimationError {
+    TooLarge { width: usize, height: usize },
+    TooManyLetters(usize),
+    Gif(image::ImageError),
+    Png(png::EncodingError),
+}
+
+impl fmt::Display for AnimationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnimationError::TooLarge { width, height } => write!(
+                f,
+                "{}x{} is too large for a gif, the limit is 65535x65535",
+                width, height
+            ),
+            AnimationError::TooManyLetters(count) => write!(
+                f,
+                "{} letters do not fit in an indexed palette, the limit is 256",
+                count
+            ),
+            AnimationError::Gif(e) => write!(f, "could not encode gif: {}", e),
+            AnimationError::Png(e) => write!(f, "could not encode png: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AnimationError {}
+
+impl From<image::ImageError> for AnimationError {
+    fn from(e: image::ImageError) -> Self {
+        AnimationError::Gif(e)
+    }
+}
+
+impl From<png::EncodingError> for AnimationError {
+    fn from(e: png::EncodingError) -> Self {
+        AnimationError::Png(e)
+    }
+}
+
+/*
+steps a SpriteGen and keeps a frame every few steps, the first frame is the state the
+recording started from. frames are kept as palette indices and only expanded to colors
+when encoding, the palette is fixed when the recording starts.
+advance can be called once per frame of the app to watch the recording
+*/
+pub struct AnimationRecorder {
+    pub settings: AnimationSettings,
+    dimensions: (usize, usize),
+    palette: Vec<[u8; 4]>,
+    letter_index: BTreeMap<char, u8>,
+    frames: Vec<Vec<u8>>,
+    steps_done: usize,
+}
+
+impl AnimationRecorder {
+    pub fn new(
+        sprite_gen: &mut SpriteGen,
+        settings: AnimationSettings,
+    ) -> Result<Self, AnimationError> {
+        let (palette, letter_index) = build_palette(&sprite_gen.char_color, settings.palette)?;
+        if settings.from_noise {
+            noise_fill(sprite_gen);
+        }
+        let mut recorder = Self {
+            settings,
+            dimensions: sprite_gen.char_texture.dimensions,
+            palette,
+            letter_index,
+            frames: vec![],
+            steps_done: 0,
+        };
+        recorder.capture(sprite_gen);
+        Ok(recorder)
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.steps_done >= self.settings.steps
+    }
+
+    pub fn steps_done(&self) -> usize {
+        self.steps_done
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    // run the steps up to the next frame and keep it
+    pub fn advance(&mut self, sprite_gen: &mut SpriteGen) {
+        if self.is_done() {
+            return;
+        }
+        let steps = self
+            .settings
+            .every
+            .max(1)
+            .min(self.settings.steps - self.steps_done);
+        for _ in 0..steps {
+            sprite_gen.apply();
+        }
+        self.steps_done += steps;
+        self.capture(sprite_gen);
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, AnimationError> {
+        match self.settings.format {
+            AnimationFormat::Gif => self.encode_gif(),
+            AnimationFormat::Apng => self.encode_apng(),
+        }
+    }
+
+    // a texture resized while recording is cut or padded to the size it started with
+    fn capture(&mut self, sprite_gen: &SpriteGen) {
+        let (width, height) = self.dimensions;
+        let texture = &sprite_gen.char_texture;
+        let mut frame = vec![0; width * height];
+        for y in 0..height.min(texture.dimensions.1) {
+            for x in 0..width.min(texture.dimensions.0) {
+                frame[y * width + x] = self.index_of(texture.get(x, y));
+            }
+        }
+        self.frames.push(frame);
+    }
+
+    fn encode_gif(&self) -> Result<Vec<u8>, AnimationError> {
+        let (width, height) = self.dimensions;
+        if width > u16::MAX as usize || height > u16::MAX as usize {
+            return Err(AnimationError::TooLarge { width, height });
+        }
+        let mut output = vec![];
+        {
+            // every frame has at most 256 colors, so the encoder makes an exact palette
+            let mut encoder = GifEncoder::new_with_speed(&mut output, 10);
+            encoder.set_repeat(Repeat::Infinite)?;
+            for frame in self.frames.iter() {
+                let buffer = RgbaImage::from_raw(width as u32, height as u32, self.rgba(frame))
+                    .expect("frame has the size it was created with");
+                let delay = Delay::from_numer_denom_ms(self.settings.frame_delay_ms.into(), 1);
+                encoder.encode_frame(Frame::from_parts(buffer, 0, 0, delay))?;
+            }
+        }
+        Ok(output)
+    }
+
+    // indexed, the palette is the same for every frame
+    fn encode_apng(&self) -> Result<Vec<u8>, AnimationError> {
+        let (width, height) = self.dimensions;
+        let mut output = vec![];
+        let mut encoder = png::Encoder::new(&mut output, width as u32, height as u32);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_palette(
+            self.palette
+                .iter()
+                .flat_map(|color| [color[0], color[1], color[2]])
+                .collect::<Vec<u8>>(),
+        );
+        encoder.set_trns(
+            self.palette
+                .iter()
+                .map(|color| color[3])
+                .collect::<Vec<u8>>(),
+        );
+        encoder.set_animated(self.frames.len() as u32, 0)?;
+        encoder.set_frame_delay(self.settings.frame_delay_ms, 1000)?;
+        let mut writer = encoder.write_header()?;
+        for frame in self.frames.iter() {
+            writer.write_image_data(frame)?;
+        }
+        writer.finish()?;
+        Ok(output)
+    }
+
+    // letters outside the palette are drawn like the fill letter
+    fn index_of(&self, letter: char) -> u8 {
+        match self.letter_index.get(&letter) {
+            Some(index) => *index,
+            None => self.letter_index[&CharTexture::FILL_CHAR],
+        }
+    }
+
+    fn rgba(&self, frame: &[u8]) -> Vec<u8> {
+        frame
+            .iter()
+            .flat_map(|index| self.palette[*index as usize])
+            .collect()
+    }
+}
+
+// step through a whole recording and encode it
+pub fn record_animation(
+    sprite_gen: &mut SpriteGen,
+    settings: AnimationSettings,
+) -> Result<Vec<u8>, AnimationError> {
+    let mut recorder = AnimationRecorder::new(sprite_gen, settings)?;
+    while !recorder.is_done() {
+        recorder.advance(sprite_gen);
+    }
+    recorder.encode()
+}
+
+type LetterIndex = BTreeMap<char, u8>;
+
+// colors as update_texture draws them in the flat render mode, transparent for letters
+// without a color. every letter gets an index, so at most 256 letters
+fn build_palette(
+    char_color: &BTreeMap<char, [u8; 4]>,
+    palette: AnimationPalette,
+) -> Result<(Vec<[u8; 4]>, LetterIndex), AnimationError> {
+    let mut letters: Vec<(char, [u8; 4])> = ('A'..='Z')
+        .chain(std::iter::once(CharTexture::FILL_CHAR))
+        .chain(char_color.keys().copied())
+        .map(|letter| {
+            let color = char_color.get(&letter).copied().unwrap_or([0, 0, 0, 0]);
+            (letter, color)
+        })
+        .collect();
+    letters.sort_by_key(|(letter, _)| *letter);
+    letters.dedup_by_key(|(letter, _)| *letter);
+    if letters.len() > 256 {
+        return Err(AnimationError::TooManyLetters(letters.len()));
+    }
+
+    match palette {
+        AnimationPalette::Letters => {}
+        AnimationPalette::Grayscale => {
+            for (_, color) in letters.iter_mut() {
+                let luminance = luminance(*color).round() as u8;
+                *color = [luminance, luminance, luminance, color[3]];
+            }
+        }
+        AnimationPalette::Reduced(count) => reduce(&mut letters, count.max(1)),
+    }
+
+    let mut colors: Vec<[u8; 4]> = vec![];
+    let mut letter_index = BTreeMap::new();
+    for (letter, color) in letters {
+        let index = match colors.iter().position(|c| *c == color) {
+            Some(index) => index,
+            None => {
+                colors.push(color);
+                colors.len() - 1
+            }
+        };
+        letter_index.insert(letter, index as u8);
+    }
+    Ok((colors, letter_index))
+}
+
+// merge the two closest colors into their average until at most count are left
+fn reduce(letters: &mut [(char, [u8; 4])], count: usize) {
+    loop {
+        let mut colors: Vec<[u8; 4]> = letters.iter().map(|(_, color)| *color).collect();
+        colors.sort_unstable();
+        colors.dedup();
+        if colors.len() <= count {
+            return;
+        }
+
+        let distance = |a: &[u8; 4], b: &[u8; 4]| -> i32 {
+            (0..3)
+                .map(|channel| (a[channel] as i32 - b[channel] as i32).pow(2))
+                .sum()
+        };
+        let mut closest = (i32::MAX, colors[0], colors[1]);
+        for (index, a) in colors.iter().enumerate() {
+            for b in colors[index + 1..].iter() {
+                if distance(a, b) < closest.0 {
+                    closest = (distance(a, b), *a, *b);
+                }
+            }
+        }
+
+        let (_, a, b) = closest;
+        let merged: [u8; 4] =
+            std::array::from_fn(|channel| ((a[channel] as u16 + b[channel] as u16) / 2) as u8);
+        for (_, color) in letters.iter_mut() {
+            if *color == a || *color == b {
+                *color = merged;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::codecs::gif::GifDecoder;
+    use image::AnimationDecoder;
+
+    use super::*;
+    use crate::rule::Rule;
+    use crate::sprite_gen::{ApplyMode, ConflictPolicy};
+
+    // A, B and C take turns in every cell, D stays and # has no color
+    fn cycling_sprite_gen() -> SpriteGen {
+        let mut sprite_gen = SpriteGen::new(5, 3);
+        sprite_gen.char_color = [
+            ('A', [200, 10, 10, 255]),
+            ('B', [10, 200, 10, 255]),
+            ('C', [10, 10, 200, 255]),
+            ('D', [90, 90, 90, 255]),
+        ]
+        .into();
+        sprite_gen.rules = [
+            ("....A....", "5B"),
+            ("....B....", "5C"),
+            ("....C....", "5A"),
+        ]
+        .iter()
+        .map(|(condition, action)| Rule::try_new(condition, action).unwrap())
+        .collect();
+        sprite_gen.apply_mode = ApplyMode::Synchronous(ConflictPolicy::LastWins);
+        sprite_gen.char_texture.pixels = "ABCD#BCDA#CDAB#".chars().collect();
+        sprite_gen.char_texture.mark_all_dirty();
+        sprite_gen
+    }
+
+    fn settings(format: AnimationFormat) -> AnimationSettings {
+        AnimationSettings {
+            format,
+            every: 2,
+            steps: 5,
+            frame_delay_ms: 70,
+            ..Default::default()
+        }
+    }
+
+    // the colors of the frames a recording with settings keeps, stepped separately
+    fn expected_frames(settings: &AnimationSettings) -> Vec<Vec<[u8; 4]>> {
+        let mut sprite_gen = cycling_sprite_gen();
+        let frame = |sprite_gen: &SpriteGen| -> Vec<[u8; 4]> {
+            let pixels = sprite_gen.char_texture.pixels.iter();
+            pixels
+                .map(|letter| sprite_gen.char_color.get(letter).copied().unwrap_or([0; 4]))
+                .collect()
+        };
+        let mut frames = vec![frame(&sprite_gen)];
+        let mut steps = 0;
+        while steps < settings.steps {
+            let every = settings.every.min(settings.steps - steps);
+            for _ in 0..every {
+                sprite_gen.apply();
+            }
+            steps += every;
+            frames.push(frame(&sprite_gen));
+        }
+        frames
+    }
+
+    #[test]
+    fn gif_frames_decode_to_the_recorded_colors() {
+        let settings = settings(AnimationFormat::Gif);
+        let expected = expected_frames(&settings);
+        let data = record_animation(&mut cycling_sprite_gen(), settings).unwrap();
+
+        let frames = GifDecoder::new(Cursor::new(data))
+            .unwrap()
+            .into_frames()
+            .collect_frames()
+            .unwrap();
+        // the first frame and one after steps 2, 4 and 5
+        assert_eq!(frames.len(), 4);
+        assert_eq!(frames.len(), expected.len());
+        for (frame, expected) in frames.iter().zip(expected) {
+            // gifs count in hundredths of a second
+            assert_eq!(frame.delay().numer_denom_ms(), (70, 1));
+            let pixels: Vec<[u8; 4]> = frame.buffer().pixels().map(|pixel| pixel.0).collect();
+            assert_eq!(pixels.len(), expected.len());
+            for (pixel, expected) in pixels.iter().zip(expected) {
+                // only full transparency survives, its color does not matter
+                match expected[3] {
+                    0 => assert_eq!(pixel[3], 0),
+                    _ => assert_eq!(*pixel, expected),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn apng_frames_decode_to_the_recorded_colors() {
+        let settings = settings(AnimationFormat::Apng);
+        let expected = expected_frames(&settings);
+        let data = record_animation(&mut cycling_sprite_gen(), settings).unwrap();
+
+        let mut decoder = png::Decoder::new(data.as_slice());
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let mut reader = decoder.read_info().unwrap();
+        let frames = reader.info().animation_control().unwrap().num_frames;
+        assert_eq!(frames as usize, expected.len());
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        for expected in expected {
+            reader.next_frame(&mut buffer).unwrap();
+            let control = reader.info().frame_control().unwrap();
+            assert_eq!((control.delay_num, control.delay_den), (70, 1000));
+            let pixels: Vec<[u8; 4]> = buffer
+                .chunks(4)
+                .map(|pixel| pixel.try_into().unwrap())
+                .collect();
+            assert_eq!(pixels, expected);
+        }
+    }
+
+    #[test]
+    fn palette_has_at_most_256_letters() {
+        let mut char_color: BTreeMap<char, [u8; 4]> = (0..229)
+            .filter_map(|code| char::from_u32(0x100 + code))
+            .map(|letter| (letter, [0, 0, 0, 255]))
+            .collect();
+        // with A to Z and the fill letter that is 256
+        assert!(build_palette(&char_color, AnimationPalette::Letters).is_ok());
+        char_color.insert('\u{3000}', [1, 2, 3, 255]);
+        assert!(matches!(
+            build_palette(&char_color, AnimationPalette::Letters),
+            Err(AnimationError::TooManyLetters(257))
+        ));
+    }
+}
@@ -0,0 +1,397 @@
+use std::{fs, path::PathBuf, process};
+
+use auto::{
+    animation::{AnimationFormat, AnimationPalette, AnimationRecorder, AnimationSettings},
+    letter_grid::{grid_to_indexed_png, grid_to_text, letter_masks, load_grid_file, mask_suffix},
+    palette::{load_palette_file, write_palette, PaletteFormat, PaletteOrder},
+    presets::PresetLibrary,
+    render::RenderMode,
+    save_and_load::{load_config_file, texture_to_png, Config},
+    sprite_gen::SpriteGen,
+    texture_noise::noise_fill,
+};
+
+const DEFAULT_DIMENSIONS: (usize, usize) = (256, 256);
+const DEFAULT_STEPS: usize = 100;
+
+const USAGE: &str =
+    "usage: headless [--config <config.json|texture.png> | --preset <name>] [--seed <u64>] [--width <px>] \
+[--height <px>] [--grid <grid.txt|grid.png>] [--steps <n>] [--until-stable] [--animate [--every <n>] [--frame-delay <ms>] \
+[--palette <letters|gray|colors>]] [--palette-file <colors.gpl|.txt|.ase|.hex> \
+[--palette-order <order|luminance>]] [--export-palette <colors.gpl|.txt|.ase|.hex>] \
+[--format <color|text|indexed|masks>] [--render <flat|neighbors|age|noise> [--depth <0..1>]] \
+--output <texture.png|animation.gif>
+  --preset        a preset shipped with the app, e.g. spirals, runs its recommended steps
+  --grid          start from a letter grid, text or an indexed png, instead of noise
+  --until-stable  stop early once the texture is stable or oscillating, --steps is the maximum
+  --animate       write every step as a frame of a gif (.gif output) or apng (any other output)
+  --every         keep a frame every n steps instead
+  --frame-delay   milliseconds per frame, gif delays are rounded down to 10ms
+  --palette       letter colors, grays, or the letter colors merged down to this many
+  --palette-file  color the letters from a GIMP, Paint.NET, Adobe or Lospec palette, in letter
+                  order (default) or matched from dark to light by luminance
+  --export-palette  also write the letter colors as a palette, the format follows the extension
+  --format        write the colors (default), the letters as text, the letters as an indexed
+                  png, or a black and white png per letter named <output>-<letter>.png
+  --render        shade each letter color by its same-letter neighbors, the steps since the cell
+                  changed (dark for 20 steps) or the starting noise, animations stay flat
+  --depth         how dark the shading gets, 0.5 by default";
+
+// what a still output holds
+#[derive(Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Color,
+    Text,
+    Indexed,
+    Masks,
+}
+
+struct HeadlessArgs {
+    config: Option<PathBuf>,
+    preset: Option<String>,
+    seed: Option<u64>,
+    width: Option<usize>,
+    height: Option<usize>,
+    grid: Option<PathBuf>,
+    palette_file: Option<PathBuf>,
+    palette_order: PaletteOrder,
+    export_palette: Option<(PathBuf, PaletteFormat)>,
+    steps: Option<usize>,
+    until_stable: bool,
+    animation: Option<AnimationSettings>,
+    format: OutputFormat,
+    render_mode: RenderMode,
+    output: PathBuf,
+}
+
+impl HeadlessArgs {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut config = None;
+        let mut preset = None;
+        let mut seed = None;
+        let mut width = None;
+        let mut height = None;
+        let mut grid = None;
+        let mut palette_file = None;
+        let mut palette_order = PaletteOrder::InOrder;
+        let mut export_palette = None;
+        let mut steps = None;
+        let mut until_stable = false;
+        let mut animate = false;
+        let mut animation = AnimationSettings::default();
+        let mut format = OutputFormat::Color;
+        let mut render = "flat".to_string();
+        let mut depth = 0.5;
+        let mut output = None;
+
+        while let Some(flag) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("missing value for {}", flag))
+            };
+            match flag.as_str() {
+                "--config" => config = Some(PathBuf::from(value()?)),
+                "--preset" => preset = Some(value()?),
+                "--seed" => {
+                    let value = value()?;
+                    seed = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("--seed expects a u64, got '{}'", value))?,
+                    )
+                }
+                "--width" => width = Some(parse_number(&value()?, "--width")?),
+                "--height" => height = Some(parse_number(&value()?, "--height")?),
+                "--grid" => grid = Some(PathBuf::from(value()?)),
+                "--steps" => steps = Some(parse_number(&value()?, "--steps")?),
+                "--until-stable" => until_stable = true,
+                "--animate" => animate = true,
+                "--every" => animation.every = parse_number(&value()?, "--every")?.max(1),
+                "--frame-delay" => {
+                    let value = value()?;
+                    animation.frame_delay_ms = value.parse().map_err(|_| {
+                        format!("--frame-delay expects milliseconds, got '{}'", value)
+                    })?
+                }
+                "--palette" => animation.palette = parse_palette(&value()?)?,
+                "--palette-file" => palette_file = Some(PathBuf::from(value()?)),
+                "--palette-order" => palette_order = parse_palette_order(&value()?)?,
+                "--export-palette" => export_palette = Some(parse_palette_path(&value()?)?),
+                "--format" => format = parse_format(&value()?)?,
+                "--render" => render = value()?,
+                "--depth" => {
+                    let value = value()?;
+                    depth = value
+                        .parse()
+                        .map_err(|_| format!("--depth expects a number, got '{}'", value))?
+                }
+                "--output" => output = Some(PathBuf::from(value()?)),
+                "--help" | "-h" => return Err(USAGE.into()),
+                _ => return Err(format!("unknown argument {}\n{}", flag, USAGE)),
+            }
+        }
+
+        if config.is_some() && preset.is_some() {
+            return Err("--config and --preset can not be used together".into());
+        }
+        if animate && format != OutputFormat::Color {
+            return Err("--animate only writes colors, leave out --format".into());
+        }
+        if width == Some(0) || height == Some(0) {
+            return Err("width and height must be at least 1".into());
+        }
+
+        Ok(Self {
+            config,
+            preset,
+            seed,
+            width,
+            height,
+            grid,
+            palette_file,
+            palette_order,
+            export_palette,
+            steps,
+            until_stable,
+            animation: animate.then_some(animation),
+            format,
+            render_mode: parse_render(&render, depth)?,
+            output: output.ok_or_else(|| format!("--output is required\n{}", USAGE))?,
+        })
+    }
+}
+
+fn parse_number(value: &str, flag: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} expects a positive number, got '{}'", flag, value))
+}
+
+fn parse_palette(value: &str) -> Result<AnimationPalette, String> {
+    match value {
+        "letters" => Ok(AnimationPalette::Letters),
+        "gray" | "grey" => Ok(AnimationPalette::Grayscale),
+        _ => value.parse().map(AnimationPalette::Reduced).map_err(|_| {
+            format!(
+                "--palette expects letters, gray or a number, got '{}'",
+                value
+            )
+        }),
+    }
+}
+
+fn parse_palette_order(value: &str) -> Result<PaletteOrder, String> {
+    match value {
+        "order" => Ok(PaletteOrder::InOrder),
+        "luminance" => Ok(PaletteOrder::ByLuminance),
+        _ => Err(format!(
+            "--palette-order expects order or luminance, got '{}'",
+            value
+        )),
+    }
+}
+
+// the format follows the extension
+fn parse_palette_path(value: &str) -> Result<(PathBuf, PaletteFormat), String> {
+    let path = PathBuf::from(value);
+    let format = path
+        .extension()
+        .and_then(|extension| PaletteFormat::from_extension(&extension.to_string_lossy()))
+        .ok_or_else(|| {
+            format!(
+                "--export-palette expects a .gpl, .txt, .ase or .hex file, got '{}'",
+                value
+            )
+        })?;
+    Ok((path, format))
+}
+
+fn parse_render(value: &str, depth: f32) -> Result<RenderMode, String> {
+    match value {
+        "flat" => Ok(RenderMode::Flat),
+        "neighbors" => Ok(RenderMode::Neighbors { depth }),
+        "age" => Ok(RenderMode::Age { depth, steps: 20 }),
+        "noise" => Ok(RenderMode::Noise { depth }),
+        _ => Err(format!(
+            "--render expects flat, neighbors, age or noise, got '{}'",
+            value
+        )),
+    }
+}
+
+fn parse_format(value: &str) -> Result<OutputFormat, String> {
+    match value {
+        "color" => Ok(OutputFormat::Color),
+        "text" => Ok(OutputFormat::Text),
+        "indexed" => Ok(OutputFormat::Indexed),
+        "masks" => Ok(OutputFormat::Masks),
+        _ => Err(format!(
+            "--format expects color, text, indexed or masks, got '{}'",
+            value
+        )),
+    }
+}
+
+// command line values win over the ones stored in the config
+fn run(args: HeadlessArgs) -> Result<(), String> {
+    let mut sprite_gen;
+    let steps;
+    let mut metadata = (String::new(), String::new());
+
+    let config = match (&args.config, &args.preset) {
+        (Some(config_path), _) => Some(
+            load_config_file(config_path)
+                .map_err(|e| format!("could not load {}: {}", config_path.display(), e))?,
+        ),
+        (_, Some(name)) => Some(preset_config(name)?),
+        _ => None,
+    };
+
+    if let Some(mut config) = config {
+        let mut dimensions = config
+            .dimensions
+            .map(|d| (d.width, d.height))
+            .unwrap_or(DEFAULT_DIMENSIONS);
+        dimensions = (
+            args.width.unwrap_or(dimensions.0),
+            args.height.unwrap_or(dimensions.1),
+        );
+        config.seed = args.seed.or(config.seed);
+        config.dimensions = None;
+
+        sprite_gen = SpriteGen::new(dimensions.0, dimensions.1);
+        config.load_into(&mut sprite_gen);
+        for (index, rule) in sprite_gen.rules.iter().enumerate() {
+            if let Some(error) = rule.error() {
+                eprintln!("rule {} is disabled: {}", index + 1, error);
+            }
+        }
+        noise_fill(&mut sprite_gen);
+        steps = args.steps.unwrap_or(config.steps);
+        metadata = (config.name, config.author);
+    } else {
+        sprite_gen = SpriteGen::new(
+            args.width.unwrap_or(DEFAULT_DIMENSIONS.0),
+            args.height.unwrap_or(DEFAULT_DIMENSIONS.1),
+        );
+        match args.seed {
+            Some(seed) => sprite_gen.randomize_with_seed(seed),
+            None => sprite_gen.randomize(),
+        }
+        steps = args.steps.unwrap_or(DEFAULT_STEPS);
+    }
+
+    // the grid has its own size, --width and --height do not apply
+    if let Some(grid_path) = &args.grid {
+        let grid = load_grid_file(grid_path)
+            .map_err(|e| format!("could not load {}: {}", grid_path.display(), e))?;
+        sprite_gen
+            .set_letters(&grid)
+            .map_err(|e| format!("could not load {}: {}", grid_path.display(), e))?;
+    }
+
+    sprite_gen.render_mode = args.render_mode;
+
+    if let Some(palette_path) = &args.palette_file {
+        let colors = load_palette_file(palette_path)
+            .map_err(|e| format!("could not load {}: {}", palette_path.display(), e))?;
+        sprite_gen.set_palette(&colors, args.palette_order);
+    }
+    if let Some((path, format)) = &args.export_palette {
+        let palette = write_palette(&sprite_gen.char_color, *format, &metadata.0);
+        fs::write(path, palette)
+            .map_err(|e| format!("could not write {}: {}", path.display(), e))?;
+    }
+
+    if let Some(mut settings) = args.animation {
+        settings.steps = steps;
+        settings.format = match args.output.extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("gif") => AnimationFormat::Gif,
+            _ => AnimationFormat::Apng,
+        };
+        let mut recorder =
+            AnimationRecorder::new(&mut sprite_gen, settings).map_err(|e| e.to_string())?;
+        while !recorder.is_done() {
+            recorder.advance(&mut sprite_gen);
+            if args.until_stable && sprite_gen.convergence.result().is_some() {
+                break;
+            }
+        }
+        if args.until_stable {
+            match sprite_gen.convergence.result() {
+                Some(convergence) => println!("{}", convergence),
+                None => println!("still changing after {} steps", steps),
+            }
+        }
+        let data = recorder.encode().map_err(|e| e.to_string())?;
+        return fs::write(&args.output, data)
+            .map_err(|e| format!("could not write {}: {}", args.output.display(), e));
+    }
+
+    if args.until_stable {
+        match sprite_gen.run_until_stable(steps) {
+            Some(convergence) => println!("{}", convergence),
+            None => println!("still changing after {} steps", steps),
+        }
+    } else {
+        for _ in 0..steps {
+            sprite_gen.apply();
+        }
+    }
+
+    let (width, height) = sprite_gen.char_texture.dimensions;
+    // the png carries its own config, so it can be fed back in with --config
+    let mut config = Config::from_sprite_gen(&sprite_gen);
+    (config.name, config.author) = metadata;
+    let texture = &sprite_gen.char_texture;
+    let write = |path: &PathBuf, data: &[u8]| {
+        fs::write(path, data).map_err(|e| format!("could not write {}: {}", path.display(), e))
+    };
+    match args.format {
+        OutputFormat::Color => {
+            let mut data = vec![255u8; width * height * 4];
+            sprite_gen.update_texture(&mut data);
+            let png = texture_to_png(&data, width, height, &config).map_err(|e| e.to_string())?;
+            write(&args.output, &png)
+        }
+        OutputFormat::Text => write(&args.output, grid_to_text(texture).as_bytes()),
+        OutputFormat::Indexed => {
+            let png = grid_to_indexed_png(texture, &sprite_gen.char_color, &config)
+                .map_err(|e| e.to_string())?;
+            write(&args.output, &png)
+        }
+        OutputFormat::Masks => {
+            let stem = args.output.with_extension("");
+            for (letter, png) in letter_masks(texture).map_err(|e| e.to_string())? {
+                let path = PathBuf::from(format!("{}-{}.png", stem.display(), mask_suffix(letter)));
+                write(&path, &png)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn preset_config(name: &str) -> Result<Config, String> {
+    let library = PresetLibrary::default();
+    match library.get(name) {
+        Some(preset) => Ok(preset.config.clone()),
+        None => Err(format!(
+            "no preset named '{}', the presets are: {}",
+            name,
+            library
+                .presets
+                .iter()
+                .map(|preset| preset.name())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        )),
+    }
+}
+
+fn main() {
+    let result = HeadlessArgs::parse(std::env::args().skip(1)).and_then(run);
+    if let Err(message) = result {
+        eprintln!("{}", message);
+        process::exit(1);
+    }
+}
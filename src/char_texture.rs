@@ -1,8 +1,42 @@
+use serde::{Deserialize, Serialize};
+
+use crate::chunks::map_row_chunks;
+
+/*
+how neighbors past the edge of the texture are looked up
+Fill: there is no neighbor, reads as FILL_CHAR and writes are dropped
+Wrap: the opposite edge, the texture is a torus and tiles seamlessly
+Mirror: reflected back into the texture, the edge itself is not repeated
+Clamp: the nearest edge cell
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Boundary {
+    #[default]
+    Fill,
+    Wrap,
+    Mirror,
+    Clamp,
+}
+
+// pixels should be changed through set or set_index, which keep track of the changed
+// cells. after writing pixels directly call mark_all_dirty
+#[derive(Clone)]
 pub struct CharTexture {
     pub pixels: Vec<char>,
     pub dimensions: (usize, usize),
     pub dimensions_i: (i32, i32),
-    pub changed: bool,
+    pub boundary: Boundary,
+    dirty: Vec<bool>,
+    dirty_cells: Vec<usize>,
+    all_dirty: bool,
+    generation: u64,
+}
+
+// cells changed since the last CharTexture::take_dirty
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Dirty {
+    All,
+    Cells(Vec<usize>),
 }
 
 impl CharTexture {
@@ -24,7 +58,11 @@ impl CharTexture {
             pixels: vec![CharTexture::FILL_CHAR; width * height],
             dimensions: (width, height),
             dimensions_i: (width as i32, height as i32),
-            changed: false,
+            boundary: Boundary::Fill,
+            dirty: vec![false; width * height],
+            dirty_cells: vec![],
+            all_dirty: true,
+            generation: 0,
         }
     }
 
@@ -33,17 +71,84 @@ impl CharTexture {
         for (index, (offset_x, offset_y)) in CharTexture::OFFSETS_3X3.into_iter().enumerate() {
             let potential_x = x as i32 + offset_x;
             let potential_y = y as i32 + offset_y;
-            if !self.out_of_range(potential_x, potential_y) {
-                result[index] = Some((potential_x as usize, potential_y as usize))
-            }
+            result[index] = self.resolve(potential_x, potential_y);
         }
         result
     }
 
+    // same as get_valid_3x3_indices for any neighborhood, see neighborhood.rs
+    pub fn get_valid_indices(
+        &self,
+        x: usize,
+        y: usize,
+        offsets: &[(i32, i32)],
+    ) -> Vec<Option<(usize, usize)>> {
+        offsets
+            .iter()
+            .map(|(offset_x, offset_y)| self.resolve(x as i32 + offset_x, y as i32 + offset_y))
+            .collect()
+    }
+
+    // map a possibly out of range position to a cell according to the boundary mode
+    pub fn resolve(&self, x: i32, y: i32) -> Option<(usize, usize)> {
+        if !self.out_of_range(x, y) {
+            return Some((x as usize, y as usize));
+        }
+        let (width, height) = self.dimensions_i;
+        match self.boundary {
+            Boundary::Fill => None,
+            Boundary::Wrap => Some((x.rem_euclid(width) as usize, y.rem_euclid(height) as usize)),
+            Boundary::Mirror => Some((mirror(x, width) as usize, mirror(y, height) as usize)),
+            Boundary::Clamp => Some((
+                x.clamp(0, width - 1) as usize,
+                y.clamp(0, height - 1) as usize,
+            )),
+        }
+    }
+
     pub fn set(&mut self, x: usize, y: usize, letter: char) {
-        self.changed = true;
         let index = self.index_from_xy(x, y);
+        self.set_index(index, letter);
+    }
+
+    // writing the letter a cell already has does not make it dirty
+    pub fn set_index(&mut self, index: usize, letter: char) {
+        if self.pixels[index] == letter {
+            return;
+        }
         self.pixels[index] = letter;
+        self.generation = self.generation.wrapping_add(1);
+        if !self.all_dirty && !self.dirty[index] {
+            self.dirty[index] = true;
+            self.dirty_cells.push(index);
+        }
+    }
+
+    pub fn mark_all_dirty(&mut self) {
+        self.all_dirty = true;
+        self.generation = self.generation.wrapping_add(1);
+    }
+
+    pub fn take_dirty(&mut self) -> Dirty {
+        for index in self.dirty_cells.iter() {
+            self.dirty[*index] = false;
+        }
+        let cells = std::mem::take(&mut self.dirty_cells);
+        if std::mem::take(&mut self.all_dirty) {
+            Dirty::All
+        } else {
+            Dirty::Cells(cells)
+        }
+    }
+
+    // cells changed since the last take_dirty, empty when all are dirty
+    pub fn dirty_cells(&self) -> &[usize] {
+        &self.dirty_cells
+    }
+
+    // changes whenever a letter does, for telling if a copy of the texture is stale
+    pub fn generation(&self) -> u64 {
+        self.generation
     }
 
     pub fn get(&self, x: usize, y: usize) -> char {
@@ -73,6 +178,39 @@ impl CharTexture {
         result
     }
 
+    pub fn stringify_offsets(&self, x: usize, y: usize, offsets: &[(i32, i32)]) -> Vec<char> {
+        offsets
+            .iter()
+            .map(|offset| self.neighbor(x, y, *offset))
+            .collect()
+    }
+
+    // letter at an offset from (x, y), FILL_CHAR if the boundary leaves no cell there
+    pub fn neighbor(&self, x: usize, y: usize, (offset_x, offset_y): (i32, i32)) -> char {
+        match self.resolve(x as i32 + offset_x, y as i32 + offset_y) {
+            Some((abs_x, abs_y)) => self.get(abs_x, abs_y),
+            None => CharTexture::FILL_CHAR,
+        }
+    }
+
+    // every cell's neighborhood concatenated, offsets.len() chars per cell
+    pub fn full_stringify_offsets(&self, offsets: &[(i32, i32)]) -> String {
+        map_row_chunks(
+            self.dimensions.0,
+            self.pixels.len(),
+            || (),
+            |_, range| {
+                let mut result = String::with_capacity(range.len() * offsets.len());
+                for index in range {
+                    let (x, y) = self.xy_from_index(index);
+                    result.extend(self.stringify_offsets(x, y, offsets).iter());
+                }
+                result
+            },
+        )
+        .concat()
+    }
+
     pub fn full_stringify(&self) -> String {
         let size = self.pixels.len();
         let mut result = String::with_capacity(size * 9);
@@ -95,13 +233,65 @@ impl CharTexture {
         result
     }
 
+    // paint a line of circles with the given radius, clipped to the texture
+    pub fn paint_line(&mut self, start: [f32; 2], end: [f32; 2], letter: char, radius: i32) {
+        let distance_squared =
+            |a: [f32; 2], b: [f32; 2]| (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2);
+        let length = distance_squared(start, end).sqrt();
+        let step = if length > 0.0 && length.is_finite() {
+            [(end[0] - start[0]) / length, (end[1] - start[1]) / length]
+        } else {
+            [0.0, 0.0]
+        };
+
+        let mut current_pixel = start;
+        let mut last_distance_squared = f32::INFINITY;
+        while distance_squared(current_pixel, end) < last_distance_squared {
+            last_distance_squared = distance_squared(current_pixel, end);
+            for x in -radius..radius {
+                for y in -radius..radius {
+                    let circle_pixel = [current_pixel[0] + x as f32, current_pixel[1] + y as f32];
+                    if distance_squared(current_pixel, circle_pixel) < radius.pow(2) as f32 {
+                        let final_x = circle_pixel[0] as i32;
+                        let final_y = circle_pixel[1] as i32;
+                        if !self.out_of_range(final_x, final_y) {
+                            self.set(final_x as usize, final_y as usize, letter);
+                        }
+                    }
+                }
+            }
+            current_pixel[0] += step[0];
+            current_pixel[1] += step[1];
+        }
+    }
+
     pub fn get_array(&self) -> &[char] {
         &self.pixels
     }
 
     pub fn resize(&mut self, width: usize, height: usize) {
+        if (width, height) == self.dimensions {
+            return;
+        }
         self.pixels.resize(width * height, CharTexture::FILL_CHAR);
         self.dimensions = (width, height);
         self.dimensions_i = (width.try_into().unwrap(), height.try_into().unwrap());
+        self.dirty = vec![false; width * height];
+        self.dirty_cells.clear();
+        self.mark_all_dirty();
+    }
+}
+
+// reflect into 0..len without repeating the edge, -1 -> 1 and len -> len - 2
+fn mirror(value: i32, len: i32) -> i32 {
+    if len == 1 {
+        return 0;
+    }
+    let period = 2 * (len - 1);
+    let folded = value.rem_euclid(period);
+    if folded < len {
+        folded
+    } else {
+        period - folded
     }
 }
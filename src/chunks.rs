@@ -0,0 +1,37 @@
+// work over a texture is split into fixed chunks of rows. off the web the chunks run on
+// rayon's thread pool, on the web one after the other. since the chunks never depend on
+// the number of threads and come back in order, neither does the result.
+use std::ops::Range;
+
+#[cfg(not(target_arch = "wasm32"))]
+use rayon::prelude::*;
+
+pub const CHUNK_ROWS: usize = 16;
+
+// calls f with each chunk's range of pixel indices, results in pixel order.
+// init makes per thread scratch state, which must not change the result
+pub fn map_row_chunks<S, T, I, F>(width: usize, pixels: usize, init: I, f: F) -> Vec<T>
+where
+    T: Send,
+    I: Fn() -> S + Sync + Send,
+    F: Fn(&mut S, Range<usize>) -> T + Sync + Send,
+{
+    let chunk_len = (width * CHUNK_ROWS).max(1);
+    let chunks = pixels.div_ceil(chunk_len);
+
+    let range = |chunk: usize| chunk * chunk_len..((chunk + 1) * chunk_len).min(pixels);
+
+    #[cfg(not(target_arch = "wasm32"))]
+    let results = (0..chunks)
+        .into_par_iter()
+        .map_init(init, |state, chunk| f(state, range(chunk)))
+        .collect();
+    #[cfg(target_arch = "wasm32")]
+    let results = {
+        let mut state = init();
+        (0..chunks)
+            .map(|chunk| f(&mut state, range(chunk)))
+            .collect()
+    };
+    results
+}
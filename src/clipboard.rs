@@ -0,0 +1,84 @@
+#[cfg(target_family = "wasm")]
+use wasm_bindgen_futures::spawn_local;
+
+use crate::pending::Pending;
+
+/*
+the system clipboard on every target
+
+the browser only hands out the clipboard text asynchronously, so reading is split in two:
+request starts a read and poll returns its result once, a few frames later on the web and
+in the next poll on desktop. writes are fire and forget on the web, failures are logged
+*/
+#[derive(Default)]
+pub struct Clipboard {
+    pending: Option<Pending<Result<String, String>>>,
+}
+
+impl Clipboard {
+    pub fn set(&self, text: &str) -> Result<(), String> {
+        #[cfg(target_family = "wasm")]
+        {
+            let clipboard = web_clipboard()?;
+            let text = text.to_owned();
+            spawn_local(async move {
+                let result =
+                    wasm_bindgen_futures::JsFuture::from(clipboard.write_text(&text)).await;
+                if result.is_err() {
+                    web_sys::console::warn_1(&"could not write the clipboard".into());
+                }
+            });
+            Ok(())
+        }
+        #[cfg(not(target_family = "wasm"))]
+        {
+            arboard::Clipboard::new()
+                .and_then(|mut clipboard| clipboard.set_text(text.to_owned()))
+                .map_err(|e| format!("could not write the clipboard: {}", e))
+        }
+    }
+
+    // start reading the clipboard, a read already in flight is dropped
+    pub fn request(&mut self) {
+        let pending = Pending::new();
+        self.pending = Some(pending.clone());
+
+        #[cfg(target_family = "wasm")]
+        match web_clipboard() {
+            Ok(clipboard) => spawn_local(async move {
+                let result = wasm_bindgen_futures::JsFuture::from(clipboard.read_text())
+                    .await
+                    .ok()
+                    .and_then(|text| text.as_string())
+                    .ok_or_else(|| "could not read the clipboard".to_string());
+                pending.set(result);
+            }),
+            Err(e) => pending.set(Err(e)),
+        }
+        #[cfg(not(target_family = "wasm"))]
+        {
+            let result = arboard::Clipboard::new()
+                .and_then(|mut clipboard| clipboard.get_text())
+                .map_err(|e| format!("could not read the clipboard: {}", e));
+            pending.set(result);
+        }
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    // the result of the last request once it is there
+    pub fn poll(&mut self) -> Option<Result<String, String>> {
+        let result = self.pending.as_ref()?.take()?;
+        self.pending = None;
+        Some(result)
+    }
+}
+
+#[cfg(target_family = "wasm")]
+fn web_clipboard() -> Result<web_sys::Clipboard, String> {
+    web_sys::window()
+        .and_then(|window| window.navigator().clipboard())
+        .ok_or_else(|| "the browser has no clipboard".to_string())
+}
@@ -0,0 +1,247 @@
+use std::path::PathBuf;
+
+use auto::save_and_load::{deserialize_config, Config};
+use serde::{Deserialize, Serialize};
+
+#[cfg(target_family = "wasm")]
+use auto::save_and_load::serialize_config;
+#[cfg(not(target_family = "wasm"))]
+use auto::save_and_load::{load_config_file, save_config_file};
+#[cfg(target_family = "wasm")]
+use wasm_bindgen::{closure::Closure, JsCast};
+
+use crate::pending::Pending;
+
+const MAX_RECENT: usize = 8;
+const RECENT_NAME: &str = "recent_configs";
+
+/*
+a config that was opened or saved. on desktop the file is read again when it is opened
+from the list, the browser has no paths so the config itself is kept
+*/
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RecentConfig {
+    File(PathBuf),
+    Browser { name: String, json: String },
+}
+
+impl RecentConfig {
+    pub fn label(&self) -> String {
+        match self {
+            RecentConfig::File(path) => path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| path.display().to_string()),
+            RecentConfig::Browser { name, .. } => name.clone(),
+        }
+    }
+}
+
+/*
+Open and Save Config and the recent configs, which are kept between sessions in the user's
+config directory on desktop and in local storage in the browser.
+desktop builds open and save the path in the text field, the browser opens a file picker
+and downloads the saved config
+*/
+pub struct ConfigFiles {
+    pub path: String,
+    pub recent: Vec<RecentConfig>,
+    picked: Option<Pending<Result<(String, Vec<u8>), String>>>,
+    // the recent list could not be stored when a config was opened, the config still opens
+    pub store_error: Option<String>,
+}
+
+impl ConfigFiles {
+    pub fn load() -> Self {
+        Self {
+            path: "config.json".into(),
+            recent: read_recent(),
+            picked: None,
+            store_error: None,
+        }
+    }
+
+    // the config at the path on desktop, in the browser the file picker opens and the
+    // config is returned by poll_open
+    pub fn open(&mut self) -> Result<Option<Config>, String> {
+        #[cfg(target_family = "wasm")]
+        {
+            let picked = Pending::new();
+            pick_file(".json,application/json", picked.clone())?;
+            self.picked = Some(picked);
+            Ok(None)
+        }
+        #[cfg(not(target_family = "wasm"))]
+        {
+            let path = PathBuf::from(self.path.trim());
+            self.open_recent(&RecentConfig::File(path)).map(Some)
+        }
+    }
+
+    // a config picked since the last call
+    pub fn poll_open(&mut self) -> Option<Result<Config, String>> {
+        let result = self.picked.as_ref()?.take()?;
+        self.picked = None;
+        Some(result.and_then(|(name, data)| {
+            let json = String::from_utf8(data).map_err(|e| format!("{}: {}", name, e))?;
+            let config = deserialize_config(&json).map_err(|e| format!("{}: {}", name, e))?;
+            self.store_error = self.remember(RecentConfig::Browser { name, json }).err();
+            Ok(config)
+        }))
+    }
+
+    pub fn open_recent(&mut self, recent: &RecentConfig) -> Result<Config, String> {
+        let config = match recent {
+            #[cfg(not(target_family = "wasm"))]
+            RecentConfig::File(path) => {
+                load_config_file(path).map_err(|e| format!("{}: {}", path.display(), e))?
+            }
+            #[cfg(target_family = "wasm")]
+            RecentConfig::File(path) => {
+                return Err(format!("can not open {} in the browser", path.display()))
+            }
+            RecentConfig::Browser { json, .. } => {
+                deserialize_config(json).map_err(|e| e.to_string())?
+            }
+        };
+        self.store_error = self.remember(recent.clone()).err();
+        Ok(config)
+    }
+
+    pub fn save(&mut self, config: &Config) -> Result<(), String> {
+        #[cfg(target_family = "wasm")]
+        {
+            let name = if config.name.is_empty() {
+                "config.json".to_string()
+            } else {
+                format!("{}.json", config.name)
+            };
+            let json = serialize_config(config);
+            crate::ui::wasm_download(
+                &format!("data:application/json;base64,{}", base64::encode(&json)),
+                &name,
+            );
+            self.remember(RecentConfig::Browser { name, json })
+        }
+        #[cfg(not(target_family = "wasm"))]
+        {
+            let mut path = PathBuf::from(self.path.trim());
+            if path.extension().is_none() {
+                path.set_extension("json");
+            }
+            save_config_file(&path, config)
+                .map_err(|e| format!("could not save {}: {}", path.display(), e))?;
+            self.remember(RecentConfig::File(path))
+        }
+    }
+
+    // move to the top of the list and store the list. the list is meant for reopening from
+    // anywhere, so files are kept with their full path
+    pub fn remember(&mut self, mut recent: RecentConfig) -> Result<(), String> {
+        if let RecentConfig::File(path) = &mut recent {
+            if let Ok(full) = path.canonicalize() {
+                *path = full;
+            }
+            self.path = path.display().to_string();
+        }
+        self.recent.retain(|other| *other != recent);
+        self.recent.insert(0, recent);
+        self.recent.truncate(MAX_RECENT);
+        write_recent(&self.recent)
+    }
+}
+
+// a missing or broken list starts empty
+fn read_recent() -> Vec<RecentConfig> {
+    read_stored(RECENT_NAME)
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+fn write_recent(recent: &[RecentConfig]) -> Result<(), String> {
+    write_stored(RECENT_NAME, &serde_json::to_string(recent).unwrap())
+}
+
+/*
+text kept between sessions under a name, in <config directory>/auto/<name>.json on desktop
+and under auto-<name> in local storage in the browser
+*/
+#[cfg(not(target_family = "wasm"))]
+fn stored_file(name: &str) -> Option<PathBuf> {
+    let directory = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(directory.join("auto").join(format!("{}.json", name)))
+}
+
+#[cfg(target_family = "wasm")]
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window().and_then(|window| window.local_storage().ok().flatten())
+}
+
+pub fn read_stored(name: &str) -> Option<String> {
+    #[cfg(target_family = "wasm")]
+    {
+        local_storage()
+            .and_then(|storage| storage.get_item(&format!("auto-{}", name)).ok().flatten())
+    }
+    #[cfg(not(target_family = "wasm"))]
+    {
+        stored_file(name).and_then(|path| std::fs::read_to_string(path).ok())
+    }
+}
+
+// without a config directory or local storage nothing is stored and nothing fails
+pub fn write_stored(name: &str, json: &str) -> Result<(), String> {
+    #[cfg(target_family = "wasm")]
+    if let Some(storage) = local_storage() {
+        storage
+            .set_item(&format!("auto-{}", name), json)
+            .map_err(|e| format!("could not store {} in local storage: {:?}", name, e))?;
+    }
+    #[cfg(not(target_family = "wasm"))]
+    if let Some(path) = stored_file(name) {
+        path.parent()
+            .map(std::fs::create_dir_all)
+            .unwrap_or(Ok(()))
+            .and_then(|_| std::fs::write(&path, json))
+            .map_err(|e| format!("could not store {} in {}: {}", name, path.display(), e))?;
+    }
+    Ok(())
+}
+
+// an invisible file input, clicked to open the browser's file picker. the name and contents
+// of the picked file arrive in picked, accept is a list of extensions and mime types
+#[cfg(target_family = "wasm")]
+pub fn pick_file(
+    accept: &str,
+    picked: Pending<Result<(String, Vec<u8>), String>>,
+) -> Result<(), String> {
+    let input = web_sys::window()
+        .and_then(|window| window.document())
+        .and_then(|document| document.create_element("input").ok())
+        .and_then(|element| element.dyn_into::<web_sys::HtmlInputElement>().ok())
+        .ok_or_else(|| "could not open a file picker".to_string())?;
+    input.set_type("file");
+    input.set_accept(accept);
+
+    let changed = input.clone();
+    let on_change = Closure::once(move || {
+        let file = match changed.files().and_then(|files| files.get(0)) {
+            Some(file) => file,
+            None => return,
+        };
+        wasm_bindgen_futures::spawn_local(async move {
+            let data = wasm_bindgen_futures::JsFuture::from(file.array_buffer())
+                .await
+                .map(|buffer| js_sys::Uint8Array::new(&buffer).to_vec())
+                .map_err(|_| format!("could not read {}", file.name()));
+            picked.set(data.map(|data| (file.name(), data)));
+        });
+    });
+    input.set_onchange(Some(on_change.as_ref().unchecked_ref()));
+    on_change.forget();
+    input.click();
+    Ok(())
+}
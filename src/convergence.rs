@@ -0,0 +1,237 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+
+use crate::char_texture::CharTexture;
+use crate::incremental::StepKey;
+
+pub const DEFAULT_MAX_PERIOD: usize = 8;
+
+// what stepping has settled into, since is the step count the pattern started at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Convergence {
+    Stable { since: usize },
+    Oscillating { period: usize, since: usize },
+}
+
+impl std::fmt::Display for Convergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Convergence::Stable { since } => write!(f, "stable after {} steps", since),
+            Convergence::Oscillating { period, since } => {
+                write!(f, "period-{} oscillation after {} steps", period, since)
+            }
+        }
+    }
+}
+
+/*
+hashes of the texture after the last max_period steps. a hash equal to the one period
+steps back means the texture is cycling with that period, 1 being a fixed point.
+painting, a new image or different rules start over.
+with random actions, the sequential mode or the random conflict policy a repeat can be
+luck, so the pattern has to hold for max_period steps in a row before it is reported
+*/
+pub struct ConvergenceTracker {
+    pub max_period: usize,
+    hashes: VecDeque<u64>,
+    key: Option<StepKey>,
+    generation: Option<u64>,
+    // the pattern of the last step and for how many steps in a row it was seen
+    pattern: Option<Convergence>,
+    held: usize,
+    result: Option<Convergence>,
+}
+
+impl ConvergenceTracker {
+    pub fn new(max_period: usize) -> Self {
+        Self {
+            max_period,
+            hashes: VecDeque::new(),
+            key: None,
+            generation: None,
+            pattern: None,
+            held: 0,
+            result: None,
+        }
+    }
+
+    pub fn result(&self) -> Option<Convergence> {
+        self.result
+    }
+
+    pub fn reset(&mut self) {
+        self.hashes.clear();
+        self.key = None;
+        self.generation = None;
+        self.pattern = None;
+        self.held = 0;
+        self.result = None;
+    }
+
+    // after every step, generation is the texture's from before the step. random is set
+    // when the same letters can give a different next step
+    pub(crate) fn record(
+        &mut self,
+        texture: &CharTexture,
+        generation: u64,
+        key: StepKey,
+        steps: usize,
+        random: bool,
+    ) {
+        if self.key.as_ref() != Some(&key) || self.generation != Some(generation) {
+            self.reset();
+            self.key = Some(key);
+        }
+        self.generation = Some(texture.generation());
+
+        // nothing was written, so no need to hash and it is a fixed point
+        let unchanged = texture.generation() == generation;
+        let hash = match self.hashes.back() {
+            Some(hash) if unchanged => *hash,
+            _ => {
+                let mut hasher = DefaultHasher::new();
+                texture.pixels.hash(&mut hasher);
+                hasher.finish()
+            }
+        };
+        let period = if unchanged {
+            Some(1)
+        } else {
+            (1..=self.hashes.len().min(self.max_period))
+                .find(|period| self.hashes[self.hashes.len() - period] == hash)
+        };
+        let pattern = match (period, self.pattern) {
+            // keep when the pattern started
+            (Some(1), Some(Convergence::Stable { since })) => Some(Convergence::Stable { since }),
+            (Some(1), _) => Some(Convergence::Stable {
+                since: steps.saturating_sub(1),
+            }),
+            (
+                Some(period),
+                Some(Convergence::Oscillating {
+                    period: last,
+                    since,
+                }),
+            ) if period == last => Some(Convergence::Oscillating { period, since }),
+            (Some(period), _) => Some(Convergence::Oscillating {
+                period,
+                since: steps.saturating_sub(period),
+            }),
+            (None, _) => None,
+        };
+        self.held = match pattern {
+            Some(_) if pattern == self.pattern => self.held + 1,
+            Some(_) => 1,
+            None => 0,
+        };
+        self.pattern = pattern;
+        self.result = pattern.filter(|_| !random || self.held >= self.max_period);
+
+        self.hashes.push_back(hash);
+        while self.hashes.len() > self.max_period {
+            self.hashes.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rule::Rule;
+    use crate::sprite_gen::{ApplyMode, ConflictPolicy, SpriteGen};
+
+    // a texture of A, rules that only look at the center cell
+    fn sprite_gen(width: usize, rules: &[(&str, &str)], mode: ApplyMode) -> SpriteGen {
+        let mut sprite_gen = SpriteGen::new(width, 1);
+        sprite_gen.set_seed(7);
+        sprite_gen.rules = rules
+            .iter()
+            .map(|(condition, action)| Rule::try_new(condition, action).unwrap())
+            .collect();
+        sprite_gen.apply_mode = mode;
+        sprite_gen.char_texture.pixels.fill('A');
+        sprite_gen.char_texture.mark_all_dirty();
+        sprite_gen
+    }
+
+    #[test]
+    fn fixed_points_are_stable() {
+        let mode = ApplyMode::Synchronous(ConflictPolicy::LastWins);
+        let mut sprite_gen = sprite_gen(8, &[("....A....", "5B")], mode);
+        sprite_gen.apply();
+        assert_eq!(sprite_gen.convergence.result(), None);
+        sprite_gen.apply();
+        assert_eq!(
+            sprite_gen.convergence.result(),
+            Some(Convergence::Stable { since: 1 })
+        );
+        // more steps keep when it started
+        sprite_gen.apply();
+        assert_eq!(
+            sprite_gen.convergence.result(),
+            Some(Convergence::Stable { since: 1 })
+        );
+    }
+
+    #[test]
+    fn oscillations_report_their_period() {
+        let mode = ApplyMode::Synchronous(ConflictPolicy::LastWins);
+        let rules = [
+            ("....A....", "5B"),
+            ("....B....", "5C"),
+            ("....C....", "5A"),
+        ];
+        let mut sprite_gen = sprite_gen(8, &rules, mode);
+        assert_eq!(
+            sprite_gen.run_until_stable(100),
+            Some(Convergence::Oscillating {
+                period: 3,
+                since: 1
+            })
+        );
+        assert_eq!(sprite_gen.steps(), 4);
+    }
+
+    #[test]
+    fn random_rules_need_the_pattern_to_hold() {
+        // two cells that turn into B at random, many steps change nothing
+        let mode = ApplyMode::Synchronous(ConflictPolicy::LastWins);
+        let mut unchanged_steps = 0;
+        for seed in 0..20 {
+            let mut sprite_gen = sprite_gen(2, &[("....A....", "5B[0.4]")], mode);
+            sprite_gen.set_seed(seed);
+            while sprite_gen.char_texture.pixels.contains(&'A') {
+                let before = sprite_gen.char_texture.pixels.clone();
+                sprite_gen.apply();
+                if sprite_gen.char_texture.pixels == before {
+                    unchanged_steps += 1;
+                }
+                assert_eq!(sprite_gen.convergence.result(), None, "seed {}", seed);
+                assert!(sprite_gen.steps() < 1000);
+            }
+
+            let done = sprite_gen.steps();
+            for _ in 1..DEFAULT_MAX_PERIOD {
+                sprite_gen.apply();
+                assert_eq!(sprite_gen.convergence.result(), None);
+            }
+            sprite_gen.apply();
+            assert_eq!(
+                sprite_gen.convergence.result(),
+                Some(Convergence::Stable { since: done })
+            );
+        }
+        assert!(unchanged_steps > 0);
+    }
+
+    #[test]
+    fn run_until_stable_stops_at_the_step_limit() {
+        // every cell flips at random, the letters never hold still for long
+        let mode = ApplyMode::Synchronous(ConflictPolicy::LastWins);
+        let rules = [("....A....", "5B[0.5]"), ("....B....", "5A[0.5]")];
+        let mut sprite_gen = sprite_gen(64, &rules, mode);
+        assert_eq!(sprite_gen.run_until_stable(50), None);
+        assert_eq!(sprite_gen.steps(), 50);
+    }
+}
@@ -0,0 +1,476 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::char_texture::Boundary;
+use crate::incremental::StepKey;
+use crate::rule::Rule;
+use crate::seed::SeededRng;
+use crate::sprite_gen::{ApplyMode, SpriteGen};
+
+pub const DEFAULT_HISTORY_BYTES: usize = 64 * 1024 * 1024;
+
+// an entry is stored as the difference to the one before it, with a full copy every so often
+// so restoring never replays more than this many differences
+const KEYFRAME_INTERVAL: usize = 32;
+
+// what was done to get from the entry before to this one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edit {
+    Start,
+    Step,
+    Image,
+    Rules,
+    Colors,
+    Seed,
+}
+
+impl std::fmt::Display for Edit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Edit::Start => "start",
+            Edit::Step => "step",
+            Edit::Image => "image",
+            Edit::Rules => "rules",
+            Edit::Colors => "colors",
+            Edit::Seed => "seed",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// everything of a SpriteGen besides the letters, the random streams are kept so stepping
+// on from a restored entry gives the same texture as the first time
+pub(crate) struct GenState {
+    pub rules: Vec<Rule>,
+    pub char_color: BTreeMap<char, [u8; 4]>,
+    pub apply_mode: ApplyMode,
+    pub boundary: Boundary,
+    pub seed: u64,
+    pub steps: usize,
+    pub rngs: [SeededRng; 3],
+}
+
+enum Letters {
+    // run length encoded
+    Key(Vec<(char, u32)>),
+    // cells that differ from the entry before
+    Delta(Vec<(u32, char)>),
+}
+
+impl Letters {
+    fn key(pixels: &[char]) -> Self {
+        let mut runs: Vec<(char, u32)> = vec![];
+        for letter in pixels {
+            match runs.last_mut() {
+                Some((last, count)) if last == letter => *count += 1,
+                _ => runs.push((*letter, 1)),
+            }
+        }
+        Letters::Key(runs)
+    }
+
+    fn delta(before: &[char], pixels: &[char]) -> Self {
+        Letters::Delta(
+            before
+                .iter()
+                .zip(pixels)
+                .enumerate()
+                .filter(|(_, (before, after))| before != after)
+                .map(|(index, (_, after))| (index as u32, *after))
+                .collect(),
+        )
+    }
+
+    fn bytes(&self) -> usize {
+        match self {
+            Letters::Key(runs) => runs.len() * std::mem::size_of::<(char, u32)>(),
+            Letters::Delta(cells) => cells.len() * std::mem::size_of::<(u32, char)>(),
+        }
+    }
+}
+
+struct Entry {
+    edit: Edit,
+    dimensions: (usize, usize),
+    letters: Letters,
+    // differences since the last key
+    chain: usize,
+    state: GenState,
+    key: StepKey,
+}
+
+impl Entry {
+    // the letters plus a rough guess for the rest
+    fn bytes(&self) -> usize {
+        let rules: usize = self
+            .state
+            .rules
+            .iter()
+            .map(|rule| rule.original_condition().len() + rule.original_action().len() + 256)
+            .sum();
+        self.letters.bytes() + rules + std::mem::size_of::<Entry>()
+    }
+}
+
+/*
+undo history of a SpriteGen
+
+record is called once per frame and adds an entry when the letters, rules, colors or
+seed changed since the entry at the cursor. with coalesce set, edits of the same kind
+in a row (a brush stroke, typing into a rule, dragging a color) replace the newest entry
+instead of adding one, steps are always kept apart.
+
+undo, redo and go_to move the cursor and restore the generator. recording while the
+cursor is not on the newest entry drops the entries after it, the new edit starts a
+branch from the restored state. once the entries take more than max_bytes the oldest
+ones are dropped.
+*/
+pub struct History {
+    pub max_bytes: usize,
+    entries: VecDeque<Entry>,
+    cursor: usize,
+    bytes: usize,
+    // letters of the entry at the cursor and the texture generation they were seen at
+    current: Vec<char>,
+    generation: u64,
+    // letters of the entry before the newest one while the newest can still be replaced
+    open: Option<Vec<char>>,
+}
+
+impl History {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            entries: VecDeque::new(),
+            cursor: 0,
+            bytes: 0,
+            current: vec![],
+            generation: 0,
+            open: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    // the edit that led to an entry and the step count at it
+    pub fn edit(&self, index: usize) -> Option<(Edit, usize)> {
+        self.entries
+            .get(index)
+            .map(|entry| (entry.edit, entry.state.steps))
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.cursor > 0
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.cursor + 1 < self.entries.len()
+    }
+
+    pub fn clear(&mut self) {
+        *self = History::new(self.max_bytes);
+    }
+
+    // add an entry if the generator changed since the entry at the cursor, true if one was
+    pub fn record(&mut self, sprite_gen: &SpriteGen, coalesce: bool) -> bool {
+        let texture = &sprite_gen.char_texture;
+        let key = StepKey::new(&sprite_gen.rules, texture.boundary, sprite_gen.apply_mode);
+        let edit = match self.entries.get(self.cursor) {
+            None => Edit::Start,
+            Some(entry) => {
+                if sprite_gen.seed() != entry.state.seed {
+                    Edit::Seed
+                } else if sprite_gen.steps() > entry.state.steps {
+                    Edit::Step
+                } else if texture.generation() != self.generation
+                    || texture.dimensions != entry.dimensions
+                    || sprite_gen.steps() != entry.state.steps
+                {
+                    Edit::Image
+                } else if key != entry.key {
+                    Edit::Rules
+                } else if sprite_gen.char_color != entry.state.char_color {
+                    Edit::Colors
+                } else {
+                    // the edit in progress, if any, is over
+                    if !coalesce {
+                        self.open = None;
+                    }
+                    return false;
+                }
+            }
+        };
+
+        // replace the newest entry, it was an unfinished edit of the same kind
+        let replace =
+            coalesce && edit != Edit::Step && self.can_coalesce(edit) && self.open.is_some();
+        if replace {
+            if let Some(entry) = self.entries.pop_back() {
+                self.bytes -= entry.bytes();
+            }
+            self.current = self.open.take().unwrap_or_default();
+            self.cursor = self.entries.len().saturating_sub(1);
+        }
+
+        // a new edit from an earlier entry drops the ones after it
+        while self.entries.len() > self.cursor + 1 {
+            if let Some(entry) = self.entries.pop_back() {
+                self.bytes -= entry.bytes();
+            }
+        }
+
+        let (letters, chain) = match self.entries.back() {
+            Some(last)
+                if last.dimensions == texture.dimensions && last.chain + 1 < KEYFRAME_INTERVAL =>
+            {
+                let delta = Letters::delta(&self.current, &texture.pixels);
+                // once half the cells differ a key is at most as big
+                if delta.bytes() < texture.pixels.len() * 4 {
+                    (delta, last.chain + 1)
+                } else {
+                    (Letters::key(&texture.pixels), 0)
+                }
+            }
+            _ => (Letters::key(&texture.pixels), 0),
+        };
+        let entry = Entry {
+            edit,
+            dimensions: texture.dimensions,
+            letters,
+            chain,
+            state: sprite_gen.state(),
+            key,
+        };
+        self.bytes += entry.bytes();
+        self.entries.push_back(entry);
+        self.cursor = self.entries.len() - 1;
+
+        let before = std::mem::replace(&mut self.current, texture.pixels.clone());
+        self.open = if coalesce && edit != Edit::Step && edit != Edit::Start {
+            Some(before)
+        } else {
+            None
+        };
+        self.generation = texture.generation();
+
+        self.drop_oldest();
+        true
+    }
+
+    pub fn undo(&mut self, sprite_gen: &mut SpriteGen) -> bool {
+        if !self.can_undo() {
+            return false;
+        }
+        self.go_to(self.cursor - 1, sprite_gen)
+    }
+
+    pub fn redo(&mut self, sprite_gen: &mut SpriteGen) -> bool {
+        if !self.can_redo() {
+            return false;
+        }
+        self.go_to(self.cursor + 1, sprite_gen)
+    }
+
+    // restore the generator to an entry, false if there is no such entry
+    pub fn go_to(&mut self, index: usize, sprite_gen: &mut SpriteGen) -> bool {
+        let pixels = match self.letters(index) {
+            Some(pixels) => pixels,
+            None => return false,
+        };
+        let entry = &self.entries[index];
+
+        let texture = &mut sprite_gen.char_texture;
+        let (width, height) = entry.dimensions;
+        texture.resize(width, height);
+        texture.pixels.copy_from_slice(&pixels);
+        texture.mark_all_dirty();
+        sprite_gen.restore_state(&entry.state);
+
+        self.cursor = index;
+        self.current = pixels;
+        self.generation = sprite_gen.char_texture.generation();
+        self.open = None;
+        true
+    }
+
+    fn can_coalesce(&self, edit: Edit) -> bool {
+        self.cursor + 1 == self.entries.len()
+            && self.entries.back().map(|entry| entry.edit) == Some(edit)
+    }
+
+    // decode the letters of an entry from the last key before it
+    fn letters(&self, index: usize) -> Option<Vec<char>> {
+        if index >= self.entries.len() {
+            return None;
+        }
+        let key = (0..=index)
+            .rev()
+            .find(|index| matches!(self.entries[*index].letters, Letters::Key(_)))?;
+        let mut pixels = vec![];
+        for entry in self.entries.range(key..=index) {
+            match &entry.letters {
+                Letters::Key(runs) => {
+                    pixels.clear();
+                    for (letter, count) in runs {
+                        pixels.resize(pixels.len() + *count as usize, *letter);
+                    }
+                }
+                Letters::Delta(cells) => {
+                    for (index, letter) in cells {
+                        pixels[*index as usize] = *letter;
+                    }
+                }
+            }
+        }
+        Some(pixels)
+    }
+
+    // drop entries from the front while over budget, keeping the one at the cursor
+    fn drop_oldest(&mut self) {
+        while self.bytes > self.max_bytes && self.cursor > 0 {
+            let second = match self.letters(1) {
+                Some(pixels) => pixels,
+                None => return,
+            };
+            if let Some(entry) = self.entries.pop_front() {
+                self.bytes -= entry.bytes();
+            }
+            self.cursor -= 1;
+            if let Some(front) = self.entries.front_mut() {
+                if let Letters::Delta(_) = front.letters {
+                    self.bytes -= front.letters.bytes();
+                    front.letters = Letters::key(&second);
+                    front.chain = 0;
+                    self.bytes += front.letters.bytes();
+                }
+            }
+        }
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History::new(DEFAULT_HISTORY_BYTES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Snapshot = (
+        Vec<char>,
+        (usize, usize),
+        Vec<String>,
+        BTreeMap<char, [u8; 4]>,
+        usize,
+    );
+
+    fn snapshot(sprite_gen: &SpriteGen) -> Snapshot {
+        (
+            sprite_gen.char_texture.pixels.clone(),
+            sprite_gen.char_texture.dimensions,
+            sprite_gen
+                .rules
+                .iter()
+                .map(|rule| format!("{} {}", rule.original_condition(), rule.original_action()))
+                .collect(),
+            sprite_gen.char_color.clone(),
+            sprite_gen.steps(),
+        )
+    }
+
+    // steps past a few keys, with painting, a resize, a rule and a color edit in between
+    fn record_session(history: &mut History, sprite_gen: &mut SpriteGen) -> Vec<Snapshot> {
+        let mut snapshots = vec![];
+        let mut record = |history: &mut History, sprite_gen: &SpriteGen| {
+            assert!(history.record(sprite_gen, false));
+            snapshots.push(snapshot(sprite_gen));
+        };
+        record(history, sprite_gen);
+        for step in 0..KEYFRAME_INTERVAL * 2 + 5 {
+            sprite_gen.apply();
+            record(history, sprite_gen);
+            match step {
+                10 => {
+                    for index in 0..20 {
+                        sprite_gen.char_texture.set_index(index * 7, 'Z');
+                    }
+                    record(history, sprite_gen);
+                }
+                20 => {
+                    sprite_gen.char_texture.resize(20, 14);
+                    record(history, sprite_gen);
+                }
+                30 => {
+                    sprite_gen.rules.pop();
+                    record(history, sprite_gen);
+                }
+                40 => {
+                    sprite_gen.char_color.insert('Z', [1, 2, 3, 255]);
+                    record(history, sprite_gen);
+                }
+                _ => {}
+            }
+        }
+        snapshots
+    }
+
+    #[test]
+    fn undo_and_redo_restore_every_entry() {
+        let mut sprite_gen = SpriteGen::new(24, 16);
+        sprite_gen.randomize_with_seed(7);
+        let mut history = History::default();
+        let snapshots = record_session(&mut history, &mut sprite_gen);
+        assert_eq!(history.len(), snapshots.len());
+        // deltas in between keys, or this only tests keys
+        let keys = history
+            .entries
+            .iter()
+            .filter(|entry| matches!(entry.letters, Letters::Key(_)))
+            .count();
+        assert!(keys >= 3 && keys < snapshots.len() / 2, "{} keys", keys);
+
+        for index in (0..snapshots.len() - 1).rev() {
+            assert!(history.undo(&mut sprite_gen));
+            assert_eq!(snapshot(&sprite_gen), snapshots[index], "undo to {}", index);
+        }
+        assert!(!history.undo(&mut sprite_gen));
+        for (index, expected) in snapshots.iter().enumerate().skip(1) {
+            assert!(history.redo(&mut sprite_gen));
+            assert_eq!(&snapshot(&sprite_gen), expected, "redo to {}", index);
+        }
+        assert!(!history.redo(&mut sprite_gen));
+
+        // stepping on from a restored entry gives the same letters as the first time
+        assert!(history.go_to(5, &mut sprite_gen));
+        sprite_gen.apply();
+        assert_eq!(snapshot(&sprite_gen), snapshots[6]);
+    }
+
+    #[test]
+    fn dropping_old_entries_keeps_the_rest_restorable() {
+        let mut sprite_gen = SpriteGen::new(24, 16);
+        sprite_gen.randomize_with_seed(7);
+        // room for a few entries only
+        let mut history = History::new(24 * 16 * 4 * 20);
+        let snapshots = record_session(&mut history, &mut sprite_gen);
+        assert!(history.len() > 5 && history.len() < snapshots.len());
+        assert!(matches!(history.entries[0].letters, Letters::Key(_)));
+
+        let dropped = snapshots.len() - history.len();
+        for index in 0..history.len() {
+            assert!(history.go_to(index, &mut sprite_gen));
+            assert_eq!(snapshot(&sprite_gen), snapshots[dropped + index]);
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use crate::char_texture::{Boundary, CharTexture, Dirty};
+use crate::neighborhood::Neighborhood;
+use crate::rule::Rule;
+use crate::sprite_gen::ApplyMode;
+
+/*
+a step only has to revisit the cells near the ones that changed in the step before.
+with r the largest offset of any rule, a cell reads and writes cells at most r away,
+across the edge too for every boundary mode, so
+    cells whose match can change: within r of a changed cell
+    cells whose letter can change (targets): within r of those
+    cells whose writes decide the targets (evaluate): within r of the targets
+cells that matched a rule with random actions last step match again and may write
+something else, so they are treated like cells whose match changed.
+every other cell gets the same writes as in the step before, which gave it its current
+letter, so it keeps it. that only holds when a step depends on nothing but the previous
+generation: the synchronous mode with LastWins or Priority. Random re-rolls conflicts
+and Sequential shuffles the rules every step, those always run the full pass.
+*/
+pub struct Region {
+    pub evaluate: Vec<bool>,
+    pub targets: Vec<bool>,
+}
+
+// past one changed cell in this many, marking the region costs more than it saves
+const MAX_CHANGED_SHARE: usize = 16;
+
+impl Region {
+    pub fn new(
+        texture: &CharTexture,
+        rules: &[Rule],
+        dirty: &Dirty,
+        active: &[usize],
+    ) -> Option<Self> {
+        let changed = match dirty {
+            Dirty::All => return None,
+            Dirty::Cells(cells) => cells,
+        };
+        let size = texture.pixels.len();
+        if (changed.len() + active.len()) * MAX_CHANGED_SHARE > size {
+            return None;
+        }
+        let radius = rules
+            .iter()
+            .filter(|rule| rule.condition().is_some())
+            .flat_map(|rule| rule.offsets())
+            .map(|(x, y)| x.abs().max(y.abs()))
+            .max()
+            .unwrap_or(0);
+        // boxes as big as the texture save nothing, and edges could fold onto each other
+        let (width, height) = texture.dimensions_i;
+        if 6 * radius + 1 > width.min(height) {
+            return None;
+        }
+
+        let mut region = Region {
+            evaluate: vec![false; size],
+            targets: vec![false; size],
+        };
+        mark_boxes(texture, &mut region.targets, changed, 2 * radius);
+        mark_boxes(texture, &mut region.targets, active, radius);
+        mark_boxes(texture, &mut region.evaluate, changed, 3 * radius);
+        mark_boxes(texture, &mut region.evaluate, active, 2 * radius);
+        Some(region)
+    }
+}
+
+// everything a step depends on besides the letters, see Region
+#[derive(Clone, PartialEq)]
+pub struct StepKey {
+    rules: Vec<(String, String, Neighborhood)>,
+    boundary: Boundary,
+    mode: ApplyMode,
+}
+
+impl StepKey {
+    pub fn new(rules: &[Rule], boundary: Boundary, mode: ApplyMode) -> Self {
+        Self {
+            rules: rules
+                .iter()
+                .map(|rule| {
+                    (
+                        rule.original_condition().to_owned(),
+                        rule.original_action().to_owned(),
+                        rule.neighborhood().clone(),
+                    )
+                })
+                .collect(),
+            boundary,
+            mode,
+        }
+    }
+}
+
+pub struct LastStep {
+    pub key: StepKey,
+    // cells that matched a rule with random actions
+    pub active: Vec<usize>,
+}
+
+fn mark_boxes(texture: &CharTexture, mask: &mut [bool], cells: &[usize], radius: i32) {
+    let (width, height) = texture.dimensions_i;
+    let wrap = texture.boundary == Boundary::Wrap;
+    for index in cells {
+        let (x, y) = texture.xy_from_index(*index);
+        for offset_y in -radius..=radius {
+            for offset_x in -radius..=radius {
+                let (mut x, mut y) = (x as i32 + offset_x, y as i32 + offset_y);
+                if wrap {
+                    x = x.rem_euclid(width);
+                    y = y.rem_euclid(height);
+                } else if texture.out_of_range(x, y) {
+                    continue;
+                }
+                mask[texture.index_from_xy(x as usize, y as usize)] = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::seed::{stream_rng, Stream};
+    use crate::sprite_gen::{ConflictPolicy, SpriteGen};
+    use rand::Rng;
+
+    // A with a few B and scattered E, so most steps only change the cells around a few patches
+    fn sparse_sprite_gen(seed: u64, rules: &[Rule], boundary: Boundary) -> SpriteGen {
+        let mut rng = stream_rng(seed, Stream::Letters);
+        let mut sprite_gen = SpriteGen::new(64, 60);
+        sprite_gen.set_seed(seed);
+        sprite_gen.rules = rules.to_vec();
+        sprite_gen.char_texture.boundary = boundary;
+        for letter in sprite_gen.char_texture.pixels.iter_mut() {
+            *letter = if rng.gen_range(0.0..1.0) < 0.02 {
+                'E'
+            } else {
+                'A'
+            };
+        }
+        // one in a corner and one on an edge, so the boxes cross them
+        sprite_gen.char_texture.pixels[0] = 'B';
+        sprite_gen.char_texture.pixels[64 * 30 + 63] = 'B';
+        let index = rng.gen_range(0..sprite_gen.char_texture.pixels.len());
+        sprite_gen.char_texture.pixels[index] = 'B';
+        sprite_gen.char_texture.mark_all_dirty();
+        sprite_gen
+    }
+
+    #[test]
+    fn incremental_steps_match_full_passes() {
+        let neighborhoods = [
+            Neighborhood::Moore(1),
+            Neighborhood::Moore(2),
+            Neighborhood::Hexagonal,
+            Neighborhood::Custom(vec![(0, 0), (3, 0), (-1, 2), (0, -1)]),
+        ];
+        let boundaries = [
+            Boundary::Fill,
+            Boundary::Wrap,
+            Boundary::Mirror,
+            Boundary::Clamp,
+        ];
+        let policies = [ConflictPolicy::LastWins, ConflictPolicy::Priority];
+        for neighborhood in neighborhoods {
+            /*
+            B grows into A and leaves C behind, which turns back into A at random. C and D write
+            to the far corners of the neighborhood and the E rules write there on every step,
+            first and last, so cells up to the full margins away decide a target
+            */
+            let offsets = neighborhood.offsets().unwrap();
+            let center = offsets.iter().position(|offset| *offset == (0, 0)).unwrap() + 1;
+            let mut e_center = ".".repeat(offsets.len());
+            e_center.replace_range(center - 1..center, "E");
+            let rules: Vec<Rule> = [
+                (e_center.as_str(), format!("[{}]F", offsets.len())),
+                ("(?:B.*){1}", format!("[{}]B[0.05]", center)),
+                ("(?:B.*){4}", format!("[{}]C", center)),
+                ("(?:C.*){2}", "C*[0.1]".to_string()),
+                ("(?:C.*){3}", format!("[{}]A", center)),
+                ("(?:C.*){1}", format!("[{}]D[0.5]", offsets.len())),
+                ("(?:D.*){2}", "[1]A".to_string()),
+                (e_center.as_str(), format!("[{}]G", offsets.len())),
+            ]
+            .iter()
+            .map(|(condition, action)| {
+                Rule::try_new(condition, action)
+                    .unwrap()
+                    .with_neighborhood(neighborhood.clone())
+            })
+            .collect();
+            for boundary in boundaries {
+                for policy in policies {
+                    let (mut changed_steps, mut incremental_steps) = (0, 0);
+                    let mut incremental = sparse_sprite_gen(5, &rules, boundary);
+                    let mut full = sparse_sprite_gen(5, &rules, boundary);
+                    incremental.apply_mode = ApplyMode::Synchronous(policy);
+                    full.apply_mode = ApplyMode::Synchronous(policy);
+                    for step in 0..30 {
+                        if let Some(last_step) = incremental.last_step() {
+                            let mut texture = incremental.char_texture.clone();
+                            let dirty = texture.take_dirty();
+                            if Region::new(&texture, &rules, &dirty, &last_step.active).is_some() {
+                                incremental_steps += 1;
+                            }
+                        }
+                        incremental.apply();
+                        let before = full.char_texture.pixels.clone();
+                        full.char_texture.mark_all_dirty();
+                        full.apply();
+                        if full.char_texture.pixels != before {
+                            changed_steps += 1;
+                        }
+                        assert!(
+                            incremental.char_texture.pixels == full.char_texture.pixels,
+                            "step {} with {:?}, {:?}, {:?}",
+                            step,
+                            neighborhood,
+                            boundary,
+                            policy
+                        );
+                    }
+                    // the texture should keep changing and most steps should take the incremental
+                    // path, otherwise this tests nothing
+                    assert!(
+                        changed_steps >= 5 && incremental_steps >= 10,
+                        "{:?}, {:?}: {} steps changed, {} incremental",
+                        neighborhood,
+                        boundary,
+                        changed_steps,
+                        incremental_steps
+                    );
+                }
+            }
+        }
+    }
+}
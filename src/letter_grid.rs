@@ -0,0 +1,391 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::{fmt, fs, io, path::Path};
+
+use crate::char_texture::CharTexture;
+use crate::save_and_load::{
+    serialize_config, Config, MAX_CELLS, MAX_DIMENSION, PNG_CONFIG_KEYWORD, PNG_SIGNATURE,
+};
+
+// the letter of every palette index of an indexed png, in index order
+pub const PNG_LETTERS_KEYWORD: &str = "auto-letters";
+
+/*
+the letters themselves instead of their colors, for engines that use the letters as material
+or id masks
+
+text: one line per row, one char per letter, top row first
+indexed png: 8 bit, one palette index per letter with the letter color as the palette entry,
+the letters in index order are stored under PNG_LETTERS_KEYWORD and the config like in
+texture_to_png
+masks: an 8 bit grayscale png per letter, 255 where the letter is and 0 elsewhere
+
+text and indexed pngs can be read back as a starting grid, see SpriteGen::set_letters
+*/
+#[derive(Debug)]
+pub enum GridError {
+    Empty,
+    InvalidLetter(char),
+    Ragged {
+        row: usize,
+        width: usize,
+        expected: usize,
+    },
+    TooManyLetters(usize),
+    TooLarge {
+        width: usize,
+        height: usize,
+    },
+    NotIndexed,
+    UnknownIndex(u8),
+    Utf8(std::str::Utf8Error),
+    Png(png::DecodingError),
+    Encoding(png::EncodingError),
+    Io(io::Error),
+}
+
+impl fmt::Display for GridError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GridError::Empty => write!(f, "letter grid is empty"),
+            GridError::InvalidLetter(letter) => write!(
+                f,
+                "{:?} is not a letter, grids can only use A to Z and {}",
+                letter,
+                CharTexture::FILL_CHAR
+            ),
+            GridError::Ragged {
+                row,
+                width,
+                expected,
+            } => write!(
+                f,
+                "row {} of the letter grid has {} letters, the first row has {}",
+                row + 1,
+                width,
+                expected
+            ),
+            GridError::TooManyLetters(count) => write!(
+                f,
+                "{} letters do not fit in an indexed png, the limit is 256",
+                count
+            ),
+            GridError::TooLarge { width, height } => write!(
+                f,
+                "letter grid {}x{} is too large, the limit is {} per side and {} cells",
+                width, height, MAX_DIMENSION, MAX_CELLS
+            ),
+            GridError::NotIndexed => write!(f, "letter grid png must be 8 bit indexed"),
+            GridError::UnknownIndex(index) => {
+                write!(f, "palette index {} has no letter", index)
+            }
+            GridError::Utf8(e) => write!(f, "letter grid is not valid text: {}", e),
+            GridError::Png(e) => write!(f, "invalid png: {}", e),
+            GridError::Encoding(e) => write!(f, "could not encode png: {}", e),
+            GridError::Io(e) => write!(f, "could not read letter grid: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for GridError {}
+
+impl From<std::str::Utf8Error> for GridError {
+    fn from(e: std::str::Utf8Error) -> Self {
+        GridError::Utf8(e)
+    }
+}
+
+impl From<png::DecodingError> for GridError {
+    fn from(e: png::DecodingError) -> Self {
+        GridError::Png(e)
+    }
+}
+
+impl From<png::EncodingError> for GridError {
+    fn from(e: png::EncodingError) -> Self {
+        GridError::Encoding(e)
+    }
+}
+
+impl From<io::Error> for GridError {
+    fn from(e: io::Error) -> Self {
+        GridError::Io(e)
+    }
+}
+
+/*
+rules and conditions only know A to Z and the fill char. digits would be read as action
+indices, regex characters would break conditions and lowercase letters are outside LetterSet
+*/
+pub fn is_grid_letter(letter: char) -> bool {
+    letter.is_ascii_uppercase() || letter == CharTexture::FILL_CHAR
+}
+
+pub fn check_grid_letters(letters: &[char]) -> Result<(), GridError> {
+    match letters.iter().find(|letter| !is_grid_letter(**letter)) {
+        Some(letter) => Err(GridError::InvalidLetter(*letter)),
+        None => Ok(()),
+    }
+}
+
+// imported grids have the same limits as config dimensions
+pub fn check_grid_size(width: usize, height: usize) -> Result<(), GridError> {
+    if width > MAX_DIMENSION || height > MAX_DIMENSION || width * height > MAX_CELLS {
+        return Err(GridError::TooLarge { width, height });
+    }
+    Ok(())
+}
+
+pub fn grid_to_text(texture: &CharTexture) -> String {
+    let (width, height) = texture.dimensions;
+    let mut text = String::with_capacity((width + 1) * height);
+    for row in texture.pixels.chunks(width) {
+        text.extend(row.iter());
+        text.push('\n');
+    }
+    text
+}
+
+// trailing empty lines and \r line endings are fine, every other row needs the same width
+pub fn grid_from_text(text: &str) -> Result<CharTexture, GridError> {
+    let rows: Vec<Vec<char>> = text
+        .trim_end_matches(['\n', '\r'])
+        .lines()
+        .map(|line| line.trim_end_matches('\r').chars().collect())
+        .collect();
+    let width = rows.first().map(|row| row.len()).unwrap_or(0);
+    if width == 0 {
+        return Err(GridError::Empty);
+    }
+    check_grid_size(width, rows.len())?;
+    if let Some((row, letters)) = rows
+        .iter()
+        .enumerate()
+        .find(|(_, letters)| letters.len() != width)
+    {
+        return Err(GridError::Ragged {
+            row,
+            width: letters.len(),
+            expected: width,
+        });
+    }
+
+    let mut texture = CharTexture::new(width, rows.len());
+    texture.pixels = rows.concat();
+    check_grid_letters(&texture.pixels)?;
+    Ok(texture)
+}
+
+// every letter with a color and every letter in the grid, sorted. a letter's palette index
+// is its position
+pub fn grid_letters(texture: &CharTexture, char_color: &BTreeMap<char, [u8; 4]>) -> Vec<char> {
+    let letters: BTreeSet<char> = char_color
+        .keys()
+        .copied()
+        .chain(texture.pixels.iter().copied())
+        .collect();
+    letters.into_iter().collect()
+}
+
+// letters without a color are transparent, like update_texture draws them
+pub fn grid_to_indexed_png(
+    texture: &CharTexture,
+    char_color: &BTreeMap<char, [u8; 4]>,
+    config: &Config,
+) -> Result<Vec<u8>, GridError> {
+    let letters = grid_letters(texture, char_color);
+    if letters.len() > 256 {
+        return Err(GridError::TooManyLetters(letters.len()));
+    }
+    let index: BTreeMap<char, u8> = letters
+        .iter()
+        .enumerate()
+        .map(|(index, letter)| (*letter, index as u8))
+        .collect();
+
+    let (width, height) = texture.dimensions;
+    let mut output = vec![];
+    let mut encoder = png::Encoder::new(&mut output, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    let colors: Vec<[u8; 4]> = letters
+        .iter()
+        .map(|letter| char_color.get(letter).copied().unwrap_or([0, 0, 0, 0]))
+        .collect();
+    encoder.set_palette(
+        colors
+            .iter()
+            .flat_map(|color| [color[0], color[1], color[2]])
+            .collect::<Vec<u8>>(),
+    );
+    encoder.set_trns(colors.iter().map(|color| color[3]).collect::<Vec<u8>>());
+    encoder.add_itxt_chunk(PNG_LETTERS_KEYWORD.to_string(), letters.iter().collect())?;
+    encoder.add_itxt_chunk(PNG_CONFIG_KEYWORD.to_string(), serialize_config(config))?;
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(
+        &texture
+            .pixels
+            .iter()
+            .map(|letter| index[letter])
+            .collect::<Vec<u8>>(),
+    )?;
+    writer.finish()?;
+    Ok(output)
+}
+
+// pngs without stored letters, e.g. painted in another tool, read index 0 as A, 1 as B and so on
+pub fn grid_from_png(data: &[u8]) -> Result<CharTexture, GridError> {
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(png::Transformations::IDENTITY);
+    let mut reader = decoder.read_info()?;
+    let info = reader.info();
+    if info.color_type != png::ColorType::Indexed || info.bit_depth != png::BitDepth::Eight {
+        return Err(GridError::NotIndexed);
+    }
+    // before the frame buffer is allocated
+    check_grid_size(info.width as usize, info.height as usize)?;
+    let mut letters: Vec<char> = ('A'..='Z').collect();
+    for chunk in info.utf8_text.iter() {
+        if chunk.keyword == PNG_LETTERS_KEYWORD {
+            letters = chunk.get_text()?.chars().collect();
+        }
+    }
+    for chunk in info.uncompressed_latin1_text.iter() {
+        if chunk.keyword == PNG_LETTERS_KEYWORD {
+            letters = chunk.text.chars().collect();
+        }
+    }
+    check_grid_letters(&letters)?;
+
+    let mut indices = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut indices)?;
+    let (width, height) = (frame.width as usize, frame.height as usize);
+    let mut texture = CharTexture::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let index = indices[y * frame.line_size + x];
+            texture.pixels[y * width + x] = *letters
+                .get(index as usize)
+                .ok_or(GridError::UnknownIndex(index))?;
+        }
+    }
+    Ok(texture)
+}
+
+// an indexed png or a text grid
+pub fn grid_from_bytes(data: &[u8]) -> Result<CharTexture, GridError> {
+    if data.starts_with(&PNG_SIGNATURE) {
+        grid_from_png(data)
+    } else {
+        grid_from_text(std::str::from_utf8(data)?)
+    }
+}
+
+pub fn load_grid_file(path: &Path) -> Result<CharTexture, GridError> {
+    grid_from_bytes(&fs::read(path)?)
+}
+
+pub fn letter_mask_png(texture: &CharTexture, letter: char) -> Result<Vec<u8>, GridError> {
+    let (width, height) = texture.dimensions;
+    let mut output = vec![];
+    let mut encoder = png::Encoder::new(&mut output, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(
+        &texture
+            .pixels
+            .iter()
+            .map(|other| if *other == letter { 255 } else { 0 })
+            .collect::<Vec<u8>>(),
+    )?;
+    writer.finish()?;
+    Ok(output)
+}
+
+// a mask for every letter in the grid, sorted by letter
+pub fn letter_masks(texture: &CharTexture) -> Result<Vec<(char, Vec<u8>)>, GridError> {
+    let letters: BTreeSet<char> = texture.pixels.iter().copied().collect();
+    letters
+        .into_iter()
+        .map(|letter| Ok((letter, letter_mask_png(texture, letter)?)))
+        .collect()
+}
+
+// part of a file name for a letter's mask, letters that file systems could mix up or reject
+// are written as their code point
+pub fn mask_suffix(letter: char) -> String {
+    match letter {
+        'A'..='Z' | '0'..='9' => letter.to_string(),
+        _ => format!("u{:04x}", letter as u32),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grids_only_take_rule_letters() {
+        assert!(grid_from_text("AB#\nZZA\n").is_ok());
+        for text in ["AB\nA1", "Ab\nAA", "A.\nAA", "AÄ\nAA"] {
+            assert!(
+                matches!(grid_from_text(text), Err(GridError::InvalidLetter(_))),
+                "{:?}",
+                text
+            );
+        }
+
+        let mut texture = CharTexture::new(2, 1);
+        texture.pixels = vec!['A', 'B'];
+        let mut sprite_gen = crate::SpriteGen::new(2, 1);
+        let config = Config::from_sprite_gen(&sprite_gen);
+        let png = grid_to_indexed_png(&texture, &BTreeMap::new(), &config).unwrap();
+        assert_eq!(grid_from_png(&png).unwrap().pixels, texture.pixels);
+
+        texture.pixels = vec!['A', '*'];
+        assert!(matches!(
+            sprite_gen.set_letters(&texture),
+            Err(GridError::InvalidLetter('*'))
+        ));
+    }
+
+    #[test]
+    fn oversized_grids_are_rejected() {
+        let row = "A".repeat(MAX_DIMENSION);
+        let text = format!("{}\n", row).repeat(MAX_CELLS / MAX_DIMENSION);
+        assert!(grid_from_text(&text).is_ok());
+        for text in [
+            format!("{}A\n", row),
+            format!("{}{}\n", text, row),
+            "A\n".repeat(MAX_DIMENSION + 1),
+        ] {
+            assert!(matches!(
+                grid_from_text(&text),
+                Err(GridError::TooLarge { .. })
+            ));
+        }
+
+        let config = Config::from_sprite_gen(&crate::SpriteGen::new(1, 1));
+        for (width, height) in [(MAX_DIMENSION + 1, 1), (MAX_DIMENSION, MAX_DIMENSION)] {
+            // the size is checked before the image data is read, so it can stay short
+            let mut png = vec![];
+            let mut encoder = png::Encoder::new(&mut png, width as u32, height as u32);
+            encoder.set_color(png::ColorType::Indexed);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.set_palette(vec![0; 3]);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_chunk(png::chunk::IDAT, &[0; 8]).unwrap();
+            drop(writer);
+            match grid_from_png(&png) {
+                Err(GridError::TooLarge {
+                    width: found_width,
+                    height: found_height,
+                }) => assert_eq!((found_width, found_height), (width, height)),
+                other => panic!("{}x{} read as {:?}", width, height, other.map(|_| ())),
+            }
+        }
+        let texture = CharTexture::new(2, 2);
+        let png = grid_to_indexed_png(&texture, &BTreeMap::new(), &config).unwrap();
+        assert!(grid_from_png(&png).is_ok());
+    }
+}
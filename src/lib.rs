@@ -0,0 +1,56 @@
+//! Texture generation core: a grid of letters, rules that rewrite it, noise
+//! for the starting image and a letter to color mapping.
+//!
+//! Everything in here is independent of bevy. The `auto` app (behind the
+//! default `gui` feature) and the `headless` binary are thin consumers.
+
+pub mod animation;
+pub mod char_texture;
+mod chunks;
+pub mod convergence;
+pub mod history;
+mod incremental;
+pub mod letter_grid;
+pub mod neighborhood;
+pub mod palette;
+pub mod presets;
+pub mod random_rules;
+pub mod render;
+pub mod rule;
+pub mod save_and_load;
+pub mod seed;
+pub mod sprite_gen;
+pub mod texture_noise;
+
+pub use animation::{
+    record_animation, AnimationError, AnimationFormat, AnimationPalette, AnimationRecorder,
+    AnimationSettings,
+};
+pub use char_texture::{Boundary, CharTexture, Dirty};
+pub use convergence::{Convergence, ConvergenceTracker};
+pub use history::{Edit, History, DEFAULT_HISTORY_BYTES};
+pub use letter_grid::{
+    check_grid_letters, check_grid_size, grid_from_bytes, grid_from_png, grid_from_text,
+    grid_letters, grid_to_indexed_png, grid_to_text, is_grid_letter, letter_mask_png, letter_masks,
+    load_grid_file, mask_suffix, GridError, PNG_LETTERS_KEYWORD,
+};
+pub use neighborhood::{Neighborhood, NeighborhoodError, MAX_OFFSETS, MAX_RADIUS};
+pub use palette::{
+    apply_palette, detect_format, load_palette_file, luminance, read_palette, write_palette,
+    LockedPalette, PaletteError, PaletteFormat, PaletteOrder,
+};
+pub use presets::{builtin_presets, render_thumbnail, Preset, PresetLibrary, THUMBNAIL_SIZE};
+pub use random_rules::{
+    ColorSettings, Harmony, LetterSettings, RuleSettings, SpriteSettings, Transparency,
+};
+pub use render::RenderMode;
+pub use rule::{Action, ActionParam, Actions, Rule, RuleError};
+pub use save_and_load::{
+    config_from_png, deserialize_config, deserialize_config_bytes, load_config_file,
+    save_config_file, serialize_config, texture_to_png, texture_to_png_base64, write_export,
+    write_export_files, Config, ConfigError, Dimensions, PaletteEntry, RuleConfig, CONFIG_VERSION,
+    MAX_CELLS, MAX_DIMENSION, PNG_CONFIG_KEYWORD,
+};
+pub use seed::{random_seed, stream_rng, SeededRng, Stream};
+pub use sprite_gen::{ApplyMode, ConflictPolicy, RuleInputs, SpriteGen};
+pub use texture_noise::noise_fill;
@@ -1,17 +1,16 @@
-mod char_texture;
+mod clipboard;
+mod config_files;
 mod paint;
-mod random_rules;
-mod rule;
-mod save_and_load;
-mod sprite_gen;
+mod pending;
+mod preset_panel;
 mod texture_display;
-mod texture_noise;
 mod ui;
 
 use crate::paint::*;
-use crate::sprite_gen::*;
 use crate::texture_display::*;
 use crate::ui::*;
+use auto::history::History;
+use auto::sprite_gen::*;
 
 use bevy::prelude::*;
 
@@ -24,9 +23,12 @@ fn main() {
         .add_startup_system(setup)
         .add_system(egui)
         .add_system(keybinds)
+        .add_system(history)
+        .add_system(file_drop)
         .add_system(paint)
         .add_system(refresh_texture)
-        .add_system(resize_texture);
+        .add_system(resize_texture)
+        .add_system(tile_preview);
 
     #[cfg(target_family = "wasm")]
     app.add_plugin(bevy_web_fullscreen::FullViewportPlugin);
@@ -56,4 +58,5 @@ fn setup(mut commands: Commands, textures: ResMut<Assets<Image>>) {
     let texture_handle = create_texture(&mut commands, textures, default_width, default_height);
     let main_texture = MainTexture::new(sprite_gen, texture_handle);
     commands.insert_resource(main_texture);
+    commands.insert_resource(History::default());
 }
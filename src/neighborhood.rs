@@ -0,0 +1,223 @@
+use serde::{Deserialize, Serialize};
+
+/*
+the cells a rule looks at, as offsets from the current cell.
+offsets are ordered row by row from the top (dy = +radius) to the bottom, left to right,
+the 1-based position in that order is what condition strings and action indices refer to.
+
+Moore(1) is the original 3x3 layout:
+1 - 2 - 3
+4 - 5 - 6
+7 - 8 - 9
+
+VonNeumann(1), the diamond of cells within manhattan distance 1:
+    1
+2 - 3 - 4
+    5
+
+Hexagonal, the 3x3 without the top right and bottom left corners,
+a hex grid stored as rows shifted by half a cell:
+1 - 2
+3 - 4 - 5
+    6 - 7
+*/
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "UncheckedNeighborhood")]
+pub enum Neighborhood {
+    Moore(u32),
+    VonNeumann(u32),
+    Hexagonal,
+    Custom(Vec<(i32, i32)>),
+}
+
+// every neighborhood fits in the square of MAX_RADIUS, larger ones would make each cell
+// read and stringify thousands of neighbors
+pub const MAX_RADIUS: u32 = 8;
+pub const MAX_OFFSETS: usize = (2 * MAX_RADIUS as usize + 1) * (2 * MAX_RADIUS as usize + 1);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NeighborhoodError {
+    RadiusTooLarge(u32),
+    OffsetTooLarge((i32, i32)),
+    TooManyOffsets(usize),
+}
+
+impl std::fmt::Display for NeighborhoodError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NeighborhoodError::RadiusTooLarge(radius) => write!(
+                f,
+                "neighborhood radius {} is larger than {}",
+                radius, MAX_RADIUS
+            ),
+            NeighborhoodError::OffsetTooLarge((x, y)) => write!(
+                f,
+                "neighborhood offset {},{} is more than {} cells away",
+                x, y, MAX_RADIUS
+            ),
+            NeighborhoodError::TooManyOffsets(count) => write!(
+                f,
+                "neighborhood has {} offsets, the limit is {}",
+                count, MAX_OFFSETS
+            ),
+        }
+    }
+}
+
+impl std::error::Error for NeighborhoodError {}
+
+// the serialized form, checked before it becomes a Neighborhood
+#[derive(Deserialize)]
+enum UncheckedNeighborhood {
+    Moore(u32),
+    VonNeumann(u32),
+    Hexagonal,
+    Custom(Vec<(i32, i32)>),
+}
+
+impl TryFrom<UncheckedNeighborhood> for Neighborhood {
+    type Error = NeighborhoodError;
+
+    fn try_from(unchecked: UncheckedNeighborhood) -> Result<Self, Self::Error> {
+        let neighborhood = match unchecked {
+            UncheckedNeighborhood::Moore(radius) => Neighborhood::Moore(radius),
+            UncheckedNeighborhood::VonNeumann(radius) => Neighborhood::VonNeumann(radius),
+            UncheckedNeighborhood::Hexagonal => Neighborhood::Hexagonal,
+            UncheckedNeighborhood::Custom(offsets) => Neighborhood::Custom(offsets),
+        };
+        neighborhood.check()?;
+        Ok(neighborhood)
+    }
+}
+
+impl Default for Neighborhood {
+    fn default() -> Self {
+        Neighborhood::Moore(1)
+    }
+}
+
+impl Neighborhood {
+    pub fn check(&self) -> Result<(), NeighborhoodError> {
+        match self {
+            Neighborhood::Moore(radius) | Neighborhood::VonNeumann(radius) => {
+                if *radius > MAX_RADIUS {
+                    return Err(NeighborhoodError::RadiusTooLarge(*radius));
+                }
+            }
+            Neighborhood::Hexagonal => {}
+            Neighborhood::Custom(offsets) => {
+                if offsets.len() > MAX_OFFSETS {
+                    return Err(NeighborhoodError::TooManyOffsets(offsets.len()));
+                }
+                if let Some(offset) = offsets
+                    .iter()
+                    .find(|(x, y)| x.unsigned_abs() > MAX_RADIUS || y.unsigned_abs() > MAX_RADIUS)
+                {
+                    return Err(NeighborhoodError::OffsetTooLarge(*offset));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // rules keep the offsets, this is only called when the neighborhood changes
+    pub fn offsets(&self) -> Result<Vec<(i32, i32)>, NeighborhoodError> {
+        self.check()?;
+        Ok(match self {
+            Neighborhood::Moore(radius) => square(*radius)?.collect(),
+            Neighborhood::VonNeumann(radius) => square(*radius)?
+                .filter(|(x, y)| x.unsigned_abs() + y.unsigned_abs() <= *radius)
+                .collect(),
+            Neighborhood::Hexagonal => square(1)?
+                .filter(|offset| *offset != (1, 1) && *offset != (-1, -1))
+                .collect(),
+            Neighborhood::Custom(offsets) => offsets.clone(),
+        })
+    }
+
+    // 0 for a neighborhood that is out of range
+    pub fn len(&self) -> usize {
+        self.offsets().map_or(0, |offsets| offsets.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // custom offsets as "x,y x,y ...", the format the rules window edits. offsets out of
+    // range are kept, rules report them as an error
+    pub fn parse_custom(text: &str) -> Option<Self> {
+        let mut offsets = vec![];
+        for pair in text.split_whitespace() {
+            let (x, y) = pair.split_once(',')?;
+            offsets.push((x.trim().parse().ok()?, y.trim().parse().ok()?));
+        }
+        if offsets.is_empty() {
+            return None;
+        }
+        Some(Neighborhood::Custom(offsets))
+    }
+
+    pub fn format_custom(&self) -> String {
+        self.offsets()
+            .unwrap_or_default()
+            .iter()
+            .map(|(x, y)| format!("{},{}", x, y))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+}
+
+fn square(radius: u32) -> Result<impl Iterator<Item = (i32, i32)>, NeighborhoodError> {
+    let radius = i32::try_from(radius).map_err(|_| NeighborhoodError::RadiusTooLarge(radius))?;
+    Ok((-radius..=radius)
+        .rev()
+        .flat_map(move |y| (-radius..=radius).map(move |x| (x, y))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn out_of_range_neighborhoods_are_rejected() {
+        assert_eq!(Neighborhood::Moore(MAX_RADIUS).len(), MAX_OFFSETS);
+        assert_eq!(
+            Neighborhood::Moore(u32::MAX).offsets(),
+            Err(NeighborhoodError::RadiusTooLarge(u32::MAX))
+        );
+        assert_eq!(
+            Neighborhood::VonNeumann(MAX_RADIUS + 1).offsets(),
+            Err(NeighborhoodError::RadiusTooLarge(MAX_RADIUS + 1))
+        );
+        assert_eq!(
+            Neighborhood::Custom(vec![(0, 0), (i32::MIN, 1)]).offsets(),
+            Err(NeighborhoodError::OffsetTooLarge((i32::MIN, 1)))
+        );
+        assert_eq!(
+            Neighborhood::Custom(vec![(0, 0); MAX_OFFSETS + 1]).offsets(),
+            Err(NeighborhoodError::TooManyOffsets(MAX_OFFSETS + 1))
+        );
+
+        // deserializing checks too
+        let json = serde_json::to_string(&Neighborhood::Moore(u32::MAX)).unwrap();
+        assert!(serde_json::from_str::<Neighborhood>(&json).is_err());
+        let json = serde_json::to_string(&Neighborhood::Custom(vec![(-9, 0)])).unwrap();
+        assert!(serde_json::from_str::<Neighborhood>(&json).is_err());
+        let json = serde_json::to_string(&Neighborhood::VonNeumann(2)).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Neighborhood>(&json).unwrap(),
+            Neighborhood::VonNeumann(2)
+        );
+
+        // and rules are disabled like with an invalid condition
+        let rule = crate::rule::Rule::new("A", "5B").with_neighborhood(Neighborhood::Moore(9));
+        assert!(rule.condition().is_none() && rule.offsets().is_empty());
+        assert!(matches!(
+            rule.error(),
+            Some(crate::rule::RuleError::Neighborhood(
+                NeighborhoodError::RadiusTooLarge(9)
+            ))
+        ));
+    }
+}
@@ -1,8 +1,11 @@
-use crate::{char_texture::CharTexture, texture_display::MainTexture, ui::UiContext};
+use crate::{
+    texture_display::{MainSprite, MainTexture},
+    ui::UiContext,
+};
 use bevy::{prelude::*, render::camera::RenderTarget};
 
 pub fn paint(
-    sprites: Query<(&Transform, &Sprite)>,
+    sprites: Query<(&Transform, &Sprite), With<MainSprite>>,
     mut main_texture: ResMut<MainTexture>,
     mut ui_context: ResMut<UiContext>,
     mouse_button_input: Res<Input<MouseButton>>,
@@ -35,12 +38,11 @@ pub fn paint(
         let current_pixel = Vec2::new(current_pixel_x, current_pixel_y);
         //println!("pixel loc: [{},{}]",current_pixel.x,current_pixel.y);
 
-        paint_line(
-            previous_pixel.unwrap_or(current_pixel),
-            current_pixel,
+        main_texture.sprite_gen.char_texture.paint_line(
+            previous_pixel.unwrap_or(current_pixel).into(),
+            current_pixel.into(),
             ui_context.paint_letter,
             ui_context.paint_radius as i32,
-            &mut main_texture.sprite_gen.char_texture,
         );
         previous_pixel = Some(current_pixel);
     }
@@ -48,29 +50,6 @@ pub fn paint(
     ui_context.last_paint_point = previous_pixel;
 }
 
-fn paint_line(start: Vec2, end: Vec2, letter: char, radius: i32, texture: &mut CharTexture) {
-    let step = (end - start).normalize_or_zero();
-    let mut current_pixel = start;
-    let mut last_distance_squared = f32::INFINITY;
-    while current_pixel.distance_squared(end) < last_distance_squared {
-        last_distance_squared = current_pixel.distance_squared(end);
-        for x in -radius..radius {
-            for y in -radius..radius {
-                let circle_pixel: Vec2 =
-                    (current_pixel.x + x as f32, current_pixel.y + y as f32).into();
-                if current_pixel.distance_squared(circle_pixel) < radius.pow(2) as f32 {
-                    let final_x = circle_pixel.x as i32;
-                    let final_y = circle_pixel.y as i32;
-                    if !texture.out_of_range(final_x, final_y) {
-                        texture.set(final_x as usize, final_y as usize, letter);
-                    }
-                }
-            }
-        }
-        current_pixel += step;
-    }
-}
-
 // https://bevy-cheatbook.github.io/cookbook/cursor2world.html#convert-cursor-to-world-coordinates
 fn get_cursor_world_coordinates(
     screen_pos: Vec2,
@@ -0,0 +1,496 @@
+use std::collections::BTreeMap;
+use std::{fmt, fs, io, path::Path};
+
+/*
+palette files from other tools, read as a list of colors and written from the letter colors
+
+Gpl: GIMP, "R G B name" lines after a "GIMP Palette" header, no alpha
+PaintNet: Paint.NET, one AARRGGBB hex color per line, ; starts a comment
+Ase: Adobe swatch exchange, binary. RGB, gray, CMYK and Lab swatches are read, RGB is written
+Hex: Lospec, one RRGGBB hex color per line, no alpha
+
+files are told apart by their contents, see detect_format
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteFormat {
+    Gpl,
+    PaintNet,
+    Ase,
+    Hex,
+}
+
+impl PaletteFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PaletteFormat::Gpl => "gpl",
+            PaletteFormat::PaintNet => "txt",
+            PaletteFormat::Ase => "ase",
+            PaletteFormat::Hex => "hex",
+        }
+    }
+
+    // ignoring case, None for anything else
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "gpl" => Some(PaletteFormat::Gpl),
+            "txt" => Some(PaletteFormat::PaintNet),
+            "ase" => Some(PaletteFormat::Ase),
+            "hex" => Some(PaletteFormat::Hex),
+            _ => None,
+        }
+    }
+
+    pub fn mime(&self) -> &'static str {
+        match self {
+            PaletteFormat::Ase => "application/octet-stream",
+            _ => "text/plain",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PaletteFormat::Gpl => "GIMP (.gpl)",
+            PaletteFormat::PaintNet => "Paint.NET (.txt)",
+            PaletteFormat::Ase => "Adobe (.ase)",
+            PaletteFormat::Hex => "Lospec (.hex)",
+        }
+    }
+}
+
+/*
+how imported colors are given to the letters
+InOrder: the first letter gets the first color and so on, colors repeat when there are
+fewer of them than letters
+ByLuminance: letters keep their order from dark to light, the darkest letter gets the
+darkest color, the lightest letter the lightest and the others spread evenly in between,
+so the texture keeps its light and dark areas
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteOrder {
+    InOrder,
+    ByLuminance,
+}
+
+// colors given to the letters instead of random ones while the palette is locked
+#[derive(Debug, Clone, PartialEq)]
+pub struct LockedPalette {
+    pub colors: Vec<[u8; 4]>,
+    pub order: PaletteOrder,
+}
+
+#[derive(Debug)]
+pub enum PaletteError {
+    Empty,
+    InvalidLine { line: usize, text: String },
+    InvalidAse(&'static str),
+    Utf8(std::str::Utf8Error),
+    Io(io::Error),
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaletteError::Empty => write!(f, "palette has no colors"),
+            PaletteError::InvalidLine { line, text } => {
+                write!(f, "line {} of the palette is not a color: '{}'", line, text)
+            }
+            PaletteError::InvalidAse(reason) => write!(f, "invalid ase palette: {}", reason),
+            PaletteError::Utf8(e) => write!(f, "palette is not valid text: {}", e),
+            PaletteError::Io(e) => write!(f, "could not read palette: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for PaletteError {}
+
+impl From<std::str::Utf8Error> for PaletteError {
+    fn from(e: std::str::Utf8Error) -> Self {
+        PaletteError::Utf8(e)
+    }
+}
+
+impl From<io::Error> for PaletteError {
+    fn from(e: io::Error) -> Self {
+        PaletteError::Io(e)
+    }
+}
+
+// editors on windows often start text files with a byte order mark
+const BOM: char = '\u{feff}';
+
+// ase and gpl have a header, Paint.NET colors are 8 hex digits and Lospec colors 6
+pub fn detect_format(data: &[u8]) -> PaletteFormat {
+    if data.starts_with(b"ASEF") {
+        return PaletteFormat::Ase;
+    }
+    let text = String::from_utf8_lossy(data);
+    let text = text.trim_start_matches(BOM);
+    if text.trim_start().starts_with("GIMP Palette") {
+        return PaletteFormat::Gpl;
+    }
+    let paint_net = text
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with(';'))
+        .map(|line| line.len() == 8)
+        .unwrap_or(false);
+    if paint_net || text.trim_start().starts_with(';') {
+        PaletteFormat::PaintNet
+    } else {
+        PaletteFormat::Hex
+    }
+}
+
+pub fn read_palette(data: &[u8]) -> Result<Vec<[u8; 4]>, PaletteError> {
+    let colors = match detect_format(data) {
+        PaletteFormat::Ase => read_ase(data)?,
+        format => {
+            let text = std::str::from_utf8(data)?.trim_start_matches(BOM);
+            match format {
+                PaletteFormat::Gpl => read_gpl(text)?,
+                PaletteFormat::PaintNet => read_hex_lines(text, ';')?,
+                _ => read_hex_lines(text, '#')?,
+            }
+        }
+    };
+    if colors.is_empty() {
+        return Err(PaletteError::Empty);
+    }
+    Ok(colors)
+}
+
+pub fn load_palette_file(path: &Path) -> Result<Vec<[u8; 4]>, PaletteError> {
+    read_palette(&fs::read(path)?)
+}
+
+// every color is named after its letter
+pub fn write_palette(
+    char_color: &BTreeMap<char, [u8; 4]>,
+    format: PaletteFormat,
+    name: &str,
+) -> Vec<u8> {
+    let name = if name.is_empty() { "auto" } else { name };
+    match format {
+        PaletteFormat::Gpl => {
+            let mut text = format!("GIMP Palette\nName: {}\nColumns: 0\n#\n", name);
+            for (letter, color) in char_color {
+                text.push_str(&format!(
+                    "{:3} {:3} {:3}\t{}\n",
+                    color[0], color[1], color[2], letter
+                ));
+            }
+            text.into_bytes()
+        }
+        PaletteFormat::PaintNet => {
+            let mut text = format!(";paint.net Palette File\n;Palette Name: {}\n", name);
+            for color in char_color.values() {
+                text.push_str(&format!(
+                    "{:02X}{:02X}{:02X}{:02X}\n",
+                    color[3], color[0], color[1], color[2]
+                ));
+            }
+            text.into_bytes()
+        }
+        PaletteFormat::Hex => char_color
+            .values()
+            .map(|color| format!("{:02x}{:02x}{:02x}\n", color[0], color[1], color[2]))
+            .collect::<String>()
+            .into_bytes(),
+        PaletteFormat::Ase => write_ase(char_color),
+    }
+}
+
+// relative luminance of the gamma encoded color, good enough for ordering
+pub fn luminance(color: [u8; 4]) -> f32 {
+    0.299 * color[0] as f32 + 0.587 * color[1] as f32 + 0.114 * color[2] as f32
+}
+
+pub fn apply_palette(
+    char_color: &mut BTreeMap<char, [u8; 4]>,
+    colors: &[[u8; 4]],
+    order: PaletteOrder,
+) {
+    if colors.is_empty() {
+        return;
+    }
+    let mut letters: Vec<(char, [u8; 4])> = char_color
+        .iter()
+        .map(|(letter, color)| (*letter, *color))
+        .collect();
+    match order {
+        PaletteOrder::InOrder => {
+            for (index, (letter, _)) in letters.iter().enumerate() {
+                char_color.insert(*letter, colors[index % colors.len()]);
+            }
+        }
+        PaletteOrder::ByLuminance => {
+            let mut colors = colors.to_vec();
+            colors.sort_by(|a, b| luminance(*a).total_cmp(&luminance(*b)));
+            letters.sort_by(|a, b| luminance(a.1).total_cmp(&luminance(b.1)));
+            // darkest to darkest and lightest to lightest, evenly spaced in between
+            let last = letters.len().saturating_sub(1).max(1) as f32;
+            for (rank, (letter, _)) in letters.iter().enumerate() {
+                let index = (rank as f32 / last * (colors.len() - 1) as f32).round() as usize;
+                char_color.insert(*letter, colors[index]);
+            }
+        }
+    }
+}
+
+// the header is the first line that is not blank
+fn read_gpl(text: &str) -> Result<Vec<[u8; 4]>, PaletteError> {
+    let mut lines = text.lines().enumerate();
+    match lines.find(|(_, line)| !line.trim().is_empty()) {
+        Some((_, line)) if line.trim() == "GIMP Palette" => {}
+        Some((index, line)) => {
+            return Err(PaletteError::InvalidLine {
+                line: index + 1,
+                text: line.trim().to_string(),
+            })
+        }
+        None => return Ok(vec![]),
+    }
+    let mut colors = vec![];
+    for (index, line) in lines {
+        let line = line.trim();
+        if line.is_empty()
+            || line.starts_with('#')
+            || line.starts_with("Name:")
+            || line.starts_with("Columns:")
+        {
+            continue;
+        }
+        let channels: Vec<u8> = line
+            .split_whitespace()
+            .take(3)
+            .filter_map(|channel| channel.parse().ok())
+            .collect();
+        match channels[..] {
+            [r, g, b] => colors.push([r, g, b, 255]),
+            _ => {
+                return Err(PaletteError::InvalidLine {
+                    line: index + 1,
+                    text: line.to_string(),
+                })
+            }
+        }
+    }
+    Ok(colors)
+}
+
+// RRGGBB or AARRGGBB per line, a leading # is skipped
+fn read_hex_lines(text: &str, comment: char) -> Result<Vec<[u8; 4]>, PaletteError> {
+    let mut colors = vec![];
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || (comment == ';' && line.starts_with(';')) {
+            continue;
+        }
+        let digits = line.trim_start_matches('#');
+        let value = u32::from_str_radix(digits, 16).ok();
+        let color = match (digits.len(), value) {
+            (6, Some(value)) => [(value >> 16) as u8, (value >> 8) as u8, value as u8, 255],
+            (8, Some(value)) => [
+                (value >> 16) as u8,
+                (value >> 8) as u8,
+                value as u8,
+                (value >> 24) as u8,
+            ],
+            _ => {
+                return Err(PaletteError::InvalidLine {
+                    line: index + 1,
+                    text: line.to_string(),
+                })
+            }
+        };
+        colors.push(color);
+    }
+    Ok(colors)
+}
+
+// big endian reads that run out of data as an error
+struct AseReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> AseReader<'a> {
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], PaletteError> {
+        if self.data.len() < count {
+            return Err(PaletteError::InvalidAse("file ends early"));
+        }
+        let (bytes, rest) = self.data.split_at(count);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, PaletteError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, PaletteError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn f32(&mut self) -> Result<f32, PaletteError> {
+        self.u32().map(f32::from_bits)
+    }
+}
+
+const ASE_COLOR_BLOCK: u16 = 0x0001;
+
+// groups are flattened, the colors come in file order
+fn read_ase(data: &[u8]) -> Result<Vec<[u8; 4]>, PaletteError> {
+    let mut reader = AseReader { data };
+    if reader.bytes(4)? != b"ASEF" {
+        return Err(PaletteError::InvalidAse("missing ASEF signature"));
+    }
+    reader.u32()?; // version
+    let blocks = reader.u32()?;
+
+    let mut colors = vec![];
+    for _ in 0..blocks {
+        let kind = reader.u16()?;
+        let length = reader.u32()? as usize;
+        let mut block = AseReader {
+            data: reader.bytes(length)?,
+        };
+        if kind != ASE_COLOR_BLOCK {
+            continue;
+        }
+        let name_length = block.u16()? as usize;
+        block.bytes(name_length * 2)?;
+        let model = block.bytes(4)?;
+        let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        let color = match model {
+            b"RGB " => {
+                let (r, g, b) = (block.f32()?, block.f32()?, block.f32()?);
+                [channel(r), channel(g), channel(b), 255]
+            }
+            b"Gray" => {
+                let gray = channel(block.f32()?);
+                [gray, gray, gray, 255]
+            }
+            b"CMYK" => {
+                let (c, m, y, k) = (block.f32()?, block.f32()?, block.f32()?, block.f32()?);
+                [
+                    channel((1.0 - c) * (1.0 - k)),
+                    channel((1.0 - m) * (1.0 - k)),
+                    channel((1.0 - y) * (1.0 - k)),
+                    255,
+                ]
+            }
+            b"LAB " => {
+                let (l, a, b) = (block.f32()?, block.f32()?, block.f32()?);
+                let [r, g, b] = lab_to_srgb(l * 100.0, a, b);
+                [channel(r), channel(g), channel(b), 255]
+            }
+            _ => return Err(PaletteError::InvalidAse("unknown color model")),
+        };
+        colors.push(color);
+    }
+    Ok(colors)
+}
+
+fn write_ase(char_color: &BTreeMap<char, [u8; 4]>) -> Vec<u8> {
+    let mut data = b"ASEF".to_vec();
+    data.extend(1u16.to_be_bytes());
+    data.extend(0u16.to_be_bytes());
+    data.extend((char_color.len() as u32).to_be_bytes());
+    for (letter, color) in char_color {
+        // utf-16 with a terminating zero
+        let name: Vec<u16> = letter.to_string().encode_utf16().chain([0]).collect();
+        let mut block = vec![];
+        block.extend((name.len() as u16).to_be_bytes());
+        block.extend(name.iter().flat_map(|unit| unit.to_be_bytes()));
+        block.extend(b"RGB ");
+        for channel in &color[..3] {
+            block.extend((*channel as f32 / 255.0).to_be_bytes());
+        }
+        block.extend(2u16.to_be_bytes()); // normal, not a global or spot color
+
+        data.extend(ASE_COLOR_BLOCK.to_be_bytes());
+        data.extend((block.len() as u32).to_be_bytes());
+        data.extend(block);
+    }
+    data
+}
+
+// CIE L*a*b* with a D50 white point to gamma encoded sRGB in 0..1
+fn lab_to_srgb(l: f32, a: f32, b: f32) -> [f32; 3] {
+    let f_y = (l + 16.0) / 116.0;
+    let f_x = f_y + a / 500.0;
+    let f_z = f_y - b / 200.0;
+    let inverse = |t: f32| {
+        if t > 6.0 / 29.0 {
+            t.powi(3)
+        } else {
+            3.0 * (6.0f32 / 29.0).powi(2) * (t - 4.0 / 29.0)
+        }
+    };
+    let (x, y, z) = (0.9642 * inverse(f_x), inverse(f_y), 0.8251 * inverse(f_z));
+    // Bradford adapted D50 XYZ to linear sRGB
+    let linear = [
+        3.1339 * x - 1.6169 * y - 0.4906 * z,
+        -0.9788 * x + 1.9161 * y + 0.0335 * z,
+        0.0719 * x - 0.2290 * y + 1.4052 * z,
+    ];
+    linear.map(|channel| {
+        if channel <= 0.0031308 {
+            12.92 * channel
+        } else {
+            1.055 * channel.powf(1.0 / 2.4) - 0.055
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gpl_header_is_found_after_blank_lines_and_a_bom() {
+        let gpl = "\u{feff}\n  \r\nGIMP Palette\r\nName: test\n#\n255 0 0 red\n  0 128 255\tblue\n";
+        assert_eq!(detect_format(gpl.as_bytes()), PaletteFormat::Gpl);
+        assert_eq!(
+            read_palette(gpl.as_bytes()).unwrap(),
+            vec![[255, 0, 0, 255], [0, 128, 255, 255]]
+        );
+        // a palette without the header is not read as one with its first line missing
+        assert!(matches!(
+            read_gpl("255 0 0\nGIMP Palette\n0 0 0\n"),
+            Err(PaletteError::InvalidLine { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn every_format_round_trips() {
+        let char_color = BTreeMap::from([
+            ('#', [0, 0, 0, 0]),
+            ('A', [255, 0, 0, 255]),
+            ('B', [0, 128, 255, 128]),
+            ('C', [1, 2, 3, 4]),
+            ('D', [255, 255, 255, 255]),
+            ('E', [17, 203, 99, 200]),
+        ]);
+        for format in [
+            PaletteFormat::Gpl,
+            PaletteFormat::PaintNet,
+            PaletteFormat::Ase,
+            PaletteFormat::Hex,
+        ] {
+            let data = write_palette(&char_color, format, "round trip");
+            assert_eq!(detect_format(&data), format);
+            // only Paint.NET stores alpha, the others read as opaque
+            let expected: Vec<[u8; 4]> = char_color
+                .values()
+                .map(|color| match format {
+                    PaletteFormat::PaintNet => *color,
+                    _ => [color[0], color[1], color[2], 255],
+                })
+                .collect();
+            assert_eq!(read_palette(&data).unwrap(), expected, "{}", format.name());
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use auto::presets::{render_thumbnail, PresetLibrary, THUMBNAIL_SIZE};
+use auto::save_and_load::Config;
+use bevy_egui::egui;
+
+use crate::config_files::{read_stored, write_stored};
+use crate::pending::Pending;
+
+const USER_PRESETS_NAME: &str = "user_presets";
+
+enum Thumbnail {
+    Rendering(Pending<Vec<u8>>),
+    Ready(egui::TextureHandle),
+}
+
+/*
+the preset library window. a thumbnail is rendered the first time its preset is shown,
+on another thread on desktop and in the frame it is asked for in the browser, so only one
+is started per frame. user presets are kept between sessions like the recent configs
+*/
+pub struct PresetPanel {
+    pub open: bool,
+    pub name: String,
+    pub library: PresetLibrary,
+    // same order as the library
+    thumbnails: Vec<Option<Thumbnail>>,
+}
+
+impl PresetPanel {
+    pub fn load() -> Self {
+        let user: Vec<Config> = read_stored(USER_PRESETS_NAME)
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        let library = PresetLibrary::new(user);
+        Self {
+            open: false,
+            name: "".into(),
+            thumbnails: library.presets.iter().map(|_| None).collect(),
+            library,
+        }
+    }
+
+    // a user preset with the same name is replaced
+    pub fn add(&mut self, config: Config) -> Result<(), String> {
+        let name = config.name.clone();
+        self.library.add(config);
+        self.thumbnails
+            .resize_with(self.library.presets.len(), || None);
+        if let Some(index) = self
+            .library
+            .presets
+            .iter()
+            .position(|preset| !preset.builtin && preset.name() == name)
+        {
+            self.thumbnails[index] = None;
+        }
+        self.store()
+    }
+
+    pub fn remove(&mut self, index: usize) -> Result<(), String> {
+        if self.library.remove(index).is_some() {
+            self.thumbnails.remove(index);
+            self.store()?;
+        }
+        Ok(())
+    }
+
+    // the thumbnail of a preset once it is rendered. a preset without one starts rendering
+    // if start is set, start is then cleared
+    pub fn thumbnail(
+        &mut self,
+        ctx: &egui::Context,
+        index: usize,
+        start: &mut bool,
+    ) -> Option<egui::TextureId> {
+        let thumbnail = &mut self.thumbnails[index];
+        if thumbnail.is_none() && *start {
+            *start = false;
+            let config = self.library.presets[index].config.clone();
+            let rendered = Pending::new();
+            #[cfg(target_family = "wasm")]
+            rendered.set(render_thumbnail(&config, THUMBNAIL_SIZE, THUMBNAIL_SIZE));
+            #[cfg(not(target_family = "wasm"))]
+            {
+                let rendered = rendered.clone();
+                std::thread::spawn(move || {
+                    rendered.set(render_thumbnail(&config, THUMBNAIL_SIZE, THUMBNAIL_SIZE))
+                });
+            }
+            *thumbnail = Some(Thumbnail::Rendering(rendered));
+        }
+
+        if let Some(Thumbnail::Rendering(rendered)) = thumbnail {
+            let data = rendered.take()?;
+            let image =
+                egui::ColorImage::from_rgba_unmultiplied([THUMBNAIL_SIZE, THUMBNAIL_SIZE], &data);
+            *thumbnail = Some(Thumbnail::Ready(ctx.load_texture(
+                format!("preset {}", self.library.presets[index].name()),
+                image,
+            )));
+        }
+        match thumbnail {
+            Some(Thumbnail::Ready(texture)) => Some(texture.id()),
+            _ => None,
+        }
+    }
+
+    fn store(&self) -> Result<(), String> {
+        write_stored(
+            USER_PRESETS_NAME,
+            &serde_json::to_string(&self.library.user_configs()).unwrap(),
+        )
+    }
+}
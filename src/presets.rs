@@ -0,0 +1,128 @@
+use crate::save_and_load::{deserialize_config, Config};
+use crate::sprite_gen::SpriteGen;
+use crate::texture_noise::noise_fill;
+
+// the presets shipped with the app, one config per file in presets/
+const BUILTIN: [&str; 5] = [
+    include_str!("../presets/spirals.json"),
+    include_str!("../presets/lichen.json"),
+    include_str!("../presets/islands.json"),
+    include_str!("../presets/cave.json"),
+    include_str!("../presets/wood_grain.json"),
+];
+
+pub const THUMBNAIL_SIZE: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preset {
+    pub config: Config,
+    // shipped with the app, can not be replaced or removed
+    pub builtin: bool,
+}
+
+impl Preset {
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+}
+
+/*
+the builtin presets followed by the ones the user added. a preset is a config with a name,
+its seed and steps are the recommended start: load it, noise fill with the seed and run
+the steps. where user presets are kept is up to the app
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct PresetLibrary {
+    pub presets: Vec<Preset>,
+}
+
+impl PresetLibrary {
+    pub fn new(user: Vec<Config>) -> Self {
+        let mut library = Self {
+            presets: builtin_presets()
+                .into_iter()
+                .map(|config| Preset {
+                    config,
+                    builtin: true,
+                })
+                .collect(),
+        };
+        for config in user {
+            library.add(config);
+        }
+        library
+    }
+
+    // by name, ignoring case
+    pub fn get(&self, name: &str) -> Option<&Preset> {
+        self.presets
+            .iter()
+            .find(|preset| preset.name().eq_ignore_ascii_case(name.trim()))
+    }
+
+    // a user preset with the same name is replaced, true if one was
+    pub fn add(&mut self, config: Config) -> bool {
+        let preset = Preset {
+            config,
+            builtin: false,
+        };
+        match self
+            .presets
+            .iter()
+            .position(|other| !other.builtin && other.name() == preset.name())
+        {
+            Some(index) => {
+                self.presets[index] = preset;
+                true
+            }
+            None => {
+                self.presets.push(preset);
+                false
+            }
+        }
+    }
+
+    // builtin presets stay
+    pub fn remove(&mut self, index: usize) -> Option<Preset> {
+        match self.presets.get(index) {
+            Some(preset) if !preset.builtin => Some(self.presets.remove(index)),
+            _ => None,
+        }
+    }
+
+    pub fn user_configs(&self) -> Vec<Config> {
+        self.presets
+            .iter()
+            .filter(|preset| !preset.builtin)
+            .map(|preset| preset.config.clone())
+            .collect()
+    }
+}
+
+impl Default for PresetLibrary {
+    fn default() -> Self {
+        PresetLibrary::new(vec![])
+    }
+}
+
+pub fn builtin_presets() -> Vec<Config> {
+    BUILTIN
+        .iter()
+        .map(|json| deserialize_config(json).expect("builtin presets are valid configs"))
+        .collect()
+}
+
+// rgba of the texture the preset starts with, drawn at a small size without touching the
+// generator the app shows
+pub fn render_thumbnail(config: &Config, width: usize, height: usize) -> Vec<u8> {
+    let mut sprite_gen = SpriteGen::new(width, height);
+    config.load_into(&mut sprite_gen);
+    sprite_gen.char_texture.resize(width, height);
+    noise_fill(&mut sprite_gen);
+    for _ in 0..config.steps {
+        sprite_gen.apply();
+    }
+    let mut data = vec![255u8; width * height * 4];
+    sprite_gen.update_texture(&mut data);
+    data
+}
@@ -1,12 +1,10 @@
-use crate::rule::Rule;
+use crate::rule::{ActionParam, Rule};
+use crate::seed::{stream_rng, SeededRng, Stream};
 use rand::{
     distributions::WeightedIndex,
-    prelude::{Distribution, SliceRandom, ThreadRng},
+    prelude::{Distribution, SliceRandom},
     Rng,
 };
-use std::collections::HashSet;
-
-use bevy::render::color::Color;
 
 /*
 make all sliders, randomize on the sliders
@@ -58,7 +56,7 @@ pub struct RuleSettings {
 }
 
 impl RuleSettings {
-    pub fn random(rng: &mut ThreadRng) -> RuleSettings {
+    pub fn random(rng: &mut SeededRng) -> RuleSettings {
         RuleSettings {
             letter_distribution: rng.gen_range(0.0..=1.0),
             letter_distribution_pref: rng.gen_range(1..=5),
@@ -75,7 +73,7 @@ impl RuleSettings {
         }
     }
 
-    pub fn generate(&self, rng: &mut ThreadRng, letters: &[char]) -> Vec<Rule> {
+    pub fn generate(&self, rng: &mut SeededRng, letters: &[char]) -> Vec<Rule> {
         let mut rules = vec![];
         for _ in 0..rng.gen_range(self.rules_range.0..self.rules_range.1) {
             // make condition and actions for rule
@@ -84,7 +82,7 @@ impl RuleSettings {
         rules
     }
 
-    pub fn generate_single(&self, rng: &mut ThreadRng, letters: &[char]) -> Rule {
+    pub fn generate_single(&self, rng: &mut SeededRng, letters: &[char]) -> Rule {
         // generate condition
         let num_letters = rng.gen_range(1..=letters.len() / 2);
         let condition_letters: Vec<char> = weighted_values(
@@ -167,10 +165,18 @@ impl RuleSettings {
                 value = *combined_choices.choose(rng).unwrap();
             }
 
+            let chance = (rng.gen_range(0.0..1.0) < self.action_chance_for_chance)
+                .then(|| rng.gen_range(0.01..0.90));
+            // a * location with a letter or index value never wrote anything before actions
+            // were parsed strictly and now writes every cell, it is left out so generated
+            // rules still do what they did. the draws stay, so do the rules after it
+            if location == '*' {
+                continue;
+            }
             actions.push(location);
             actions.push(value);
-            if rng.gen_range(0.0..1.0) < self.action_chance_for_chance {
-                actions.push_str(&format!(r"[{:.2}]", rng.gen_range(0.01..0.90)));
+            if let Some(chance) = chance {
+                actions.push_str(&format!(r"[{:.2}]", chance));
             }
         }
 
@@ -178,73 +184,319 @@ impl RuleSettings {
     }
 }
 
+/*
+see-through letters, for decals and overlays. every letter gets an alpha in alpha_range with
+this chance and stays opaque otherwise. with no chance nothing is drawn from the rng, so the
+colors of a seed stay the same
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transparency {
+    pub chance: f32,
+    pub alpha_range: (u8, u8),
+}
+
+impl Default for Transparency {
+    fn default() -> Self {
+        Self {
+            chance: 0.0,
+            alpha_range: (64, 192),
+        }
+    }
+}
+
+/*
+how generate picks the colors
+Random: random primaries, the other letters are accents close to one of them
+Complementary: one random hue and the hue opposite it, the letters take turns
+Triadic: like complementary with three hues a third of the color wheel apart
+Analogous: hues within spread degrees around one random hue
+Monochrome: one random hue from dark to light
+Gradient: from one color to the other, alpha included, in even OKLab steps
+OkLabRamp: a random dark color to a random light color in even OKLab steps
+OkLchRamp: dark to light at one chroma while the hue turns, in even OKLCH steps
+ramps and gradients go in letter order
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Harmony {
+    #[default]
+    Random,
+    Complementary,
+    Triadic,
+    Analogous {
+        spread: f32,
+    },
+    Monochrome,
+    Gradient {
+        from: [u8; 4],
+        to: [u8; 4],
+    },
+    OkLabRamp,
+    OkLchRamp,
+}
+
+impl Harmony {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Harmony::Random => "Random",
+            Harmony::Complementary => "Complementary",
+            Harmony::Triadic => "Triadic",
+            Harmony::Analogous { .. } => "Analogous",
+            Harmony::Monochrome => "Monochrome",
+            Harmony::Gradient { .. } => "Gradient",
+            Harmony::OkLabRamp => "OKLab ramp",
+            Harmony::OkLchRamp => "OKLCH ramp",
+        }
+    }
+}
+
 pub struct ColorSettings {
     pub color_primary_accent_ratio: f32,
     pub color_hue_sat_buffer: f32,
+    pub harmony: Harmony,
+    pub transparency: Transparency,
 }
 
 impl ColorSettings {
-    pub fn random(rng: &mut ThreadRng) -> ColorSettings {
+    pub fn random(rng: &mut SeededRng) -> ColorSettings {
         ColorSettings {
             color_primary_accent_ratio: rng.gen_range(0.05..0.1),
             color_hue_sat_buffer: 0.1,
+            harmony: Harmony::default(),
+            transparency: Transparency::default(),
         }
     }
 
-    pub fn generate(&self, rng: &mut ThreadRng, letters: &[char]) -> Vec<(char, [u8; 4])> {
-        let mut primaries: Vec<[f32; 3]> = vec![];
-        let mut accents: Vec<[f32; 3]> = vec![];
+    // the rules decide which letters share a primary color
+    pub fn generate(
+        &self,
+        rng: &mut SeededRng,
+        letters: &[char],
+        rules: &[Rule],
+    ) -> Vec<(char, [u8; 4])> {
+        let mut colors: Vec<(char, [u8; 4])> = letters
+            .iter()
+            .copied()
+            .zip(self.harmony_colors(rng, letters, rules))
+            .collect();
+
+        let Transparency {
+            chance,
+            alpha_range,
+        } = self.transparency;
+        if chance > 0.0 {
+            let (low, high) = (
+                alpha_range.0.min(alpha_range.1),
+                alpha_range.0.max(alpha_range.1),
+            );
+            for (_, color) in colors.iter_mut() {
+                if rng.gen_range(0.0..1.0) < chance {
+                    color[3] = rng.gen_range(low..=high);
+                }
+            }
+        }
+        colors
+    }
+
+    fn harmony_colors(
+        &self,
+        rng: &mut SeededRng,
+        letters: &[char],
+        rules: &[Rule],
+    ) -> Vec<[u8; 4]> {
+        let count = letters.len();
+        // where a letter is on a ramp, 0 for the first and 1 for the last
+        let ramp = |index: usize| {
+            if count > 1 {
+                index as f32 / (count - 1) as f32
+            } else {
+                0.5
+            }
+        };
+        let saturation = 0.35..0.9;
+        let lightness = 0.25..0.75;
+        match self.harmony {
+            Harmony::Random => {
+                return self.random_colors(rng, &letter_interactions(rules, letters))
+            }
+            Harmony::Complementary => wheel_colors(rng, count, &[0.0, 180.0]),
+            Harmony::Triadic => wheel_colors(rng, count, &[0.0, 120.0, 240.0]),
+            Harmony::Analogous { spread } => {
+                let hue = rng.gen_range(0.0..360.0);
+                (0..count)
+                    .map(|index| {
+                        hsl_to_rgb(
+                            (hue + spread * (ramp(index) - 0.5)).rem_euclid(360.0),
+                            rng.gen_range(saturation.clone()),
+                            rng.gen_range(lightness.clone()),
+                        )
+                    })
+                    .collect()
+            }
+            Harmony::Monochrome => {
+                let hue = rng.gen_range(0.0..360.0);
+                let saturation = rng.gen_range(saturation);
+                (0..count)
+                    .map(|index| hsl_to_rgb(hue, saturation, 0.15 + 0.7 * ramp(index)))
+                    .collect()
+            }
+            Harmony::Gradient { from, to } => {
+                let channel = |color: [u8; 4], index: usize| color[index] as f32 / 255.0;
+                let (from_lab, to_lab) = (
+                    srgb_to_oklab([channel(from, 0), channel(from, 1), channel(from, 2)]),
+                    srgb_to_oklab([channel(to, 0), channel(to, 1), channel(to, 2)]),
+                );
+                return (0..count)
+                    .map(|index| {
+                        let t = ramp(index);
+                        let mut color = to_u8(oklab_to_srgb(lerp3(from_lab, to_lab, t)));
+                        color[3] =
+                            (from[3] as f32 + (to[3] as f32 - from[3] as f32) * t).round() as u8;
+                        color
+                    })
+                    .collect();
+            }
+            Harmony::OkLabRamp => {
+                let mut endpoint = |lightness| {
+                    srgb_to_oklab(hsl_to_rgb(
+                        rng.gen_range(0.0..360.0),
+                        rng.gen_range(saturation.clone()),
+                        rng.gen_range(lightness),
+                    ))
+                };
+                let (dark, light) = (endpoint(0.1..0.3), endpoint(0.7..0.9));
+                (0..count)
+                    .map(|index| oklab_to_srgb(lerp3(dark, light, ramp(index))))
+                    .collect()
+            }
+            Harmony::OkLchRamp => {
+                let hue = rng.gen_range(0.0..360.0f32);
+                let turn = rng.gen_range(60.0..180.0) * if rng.gen_bool(0.5) { 1.0 } else { -1.0 };
+                let chroma = rng.gen_range(0.05..0.15);
+                (0..count)
+                    .map(|index| {
+                        let t = ramp(index);
+                        let hue = (hue + turn * t).to_radians();
+                        oklab_to_srgb([0.3 + 0.6 * t, chroma * hue.cos(), chroma * hue.sin()])
+                    })
+                    .collect()
+            }
+        }
+        .into_iter()
+        .map(to_u8)
+        .collect()
+    }
+
+    // interactions has a row for every letter, see letter_interactions
+    fn random_colors(&self, rng: &mut SeededRng, interactions: &[Vec<u32>]) -> Vec<[u8; 4]> {
+        // a primary followed by its accents
+        let mut groups: Vec<Vec<[f32; 3]>> = vec![];
 
         //color_primary_accent_ratio: rng.gen_range(0.1..1.0),
         //color_accent_max_offset: rng.gen_range(0..100),
         let primary_hue_sat_range = self.color_hue_sat_buffer..1.0 - self.color_hue_sat_buffer;
-        for _ in 0..letters.len() {
-            if primaries.is_empty() || rng.gen_range(0.0..1.0) < self.color_primary_accent_ratio {
+        for _ in 0..interactions.len() {
+            if groups.is_empty() || rng.gen_range(0.0..1.0) < self.color_primary_accent_ratio {
                 // primary
-                primaries.push([
+                groups.push(vec![[
                     rng.gen_range(0.0..360.0),
                     rng.gen_range(primary_hue_sat_range.clone()),
                     rng.gen_range(primary_hue_sat_range.clone()),
-                ]);
+                ]]);
             } else {
                 // accent, modify an existing color
-                let mut accent = *primaries.choose(rng).unwrap();
+                let group = groups.choose_mut(rng).unwrap();
+                let mut accent = group[0];
                 accent[1] += rng
                     .gen_range(-self.color_hue_sat_buffer / 2.0..self.color_hue_sat_buffer / 2.0);
                 accent[2] += rng
                     .gen_range(-self.color_hue_sat_buffer / 2.0..self.color_hue_sat_buffer / 2.0);
-                accents.push(accent);
+                group.push(accent);
             }
         }
 
-        // hsl to rgb
-        letters
-            .iter()
-            .copied()
-            .zip(
-                primaries
-                    .into_iter()
-                    .chain(accents.into_iter())
-                    .map(|c| Color::hsl(c[0], c[1], c[2]).as_rgba_f32())
-                    .map(|r| {
-                        [
-                            ((r[0] * 255.0).round() as u8),
-                            ((r[1] * 255.0).round() as u8),
-                            ((r[2] * 255.0).round() as u8),
-                            255,
-                        ]
-                    }),
-            )
-            .collect()
+        // hsl to rgb, letters that interact get colors of the same group
+        let sizes: Vec<usize> = groups.iter().map(Vec::len).collect();
+        let mut colors = vec![[0, 0, 0, 255]; interactions.len()];
+        for (group, members) in groups.iter().zip(cluster_letters(interactions, &sizes)) {
+            for (c, letter) in group.iter().zip(members) {
+                colors[letter] = to_u8(hsl_to_rgb(c[0], c[1], c[2]));
+            }
+        }
+        colors
     }
 }
 
+/*
+how often two letters meet in a rule, row and column in letter order. the letters of a rule
+are the ones in its condition and the letters its actions look for or write, every pair of
+them counts once per rule
+*/
+fn letter_interactions(rules: &[Rule], letters: &[char]) -> Vec<Vec<u32>> {
+    let mut interactions = vec![vec![0; letters.len()]; letters.len()];
+    for rule in rules {
+        let action_letters = rule.action().iter().flat_map(|action| {
+            [&action.location, &action.value]
+                .into_iter()
+                .filter_map(|param| match param {
+                    ActionParam::Char(letter) => Some(*letter),
+                    _ => None,
+                })
+        });
+        let mut used: Vec<usize> = rule
+            .original_condition()
+            .chars()
+            .chain(action_letters)
+            .filter_map(|letter| letters.iter().position(|other| *other == letter))
+            .collect();
+        used.sort_unstable();
+        used.dedup();
+        for (index, a) in used.iter().enumerate() {
+            for b in &used[index + 1..] {
+                interactions[*a][*b] += 1;
+                interactions[*b][*a] += 1;
+            }
+        }
+    }
+    interactions
+}
+
+/*
+split the letters into groups of the given sizes, letters that interact more end up in the
+same group. the largest group is filled first: it starts with the letter that interacts most
+with the letters left and takes the letter that interacts most with its members until it is
+full, ties go to the earlier letter. the groups come back in the order of sizes, as letter
+indices starting with the one the group started from
+*/
+fn cluster_letters(interactions: &[Vec<u32>], sizes: &[usize]) -> Vec<Vec<usize>> {
+    let mut left: Vec<usize> = (0..interactions.len()).collect();
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|group| std::cmp::Reverse(sizes[*group]));
+
+    let mut groups = vec![vec![]; sizes.len()];
+    for group in order {
+        let members: &mut Vec<usize> = &mut groups[group];
+        while members.len() < sizes[group] && !left.is_empty() {
+            let with = if members.is_empty() { &left } else { &*members };
+            let score = |letter: usize| -> u32 {
+                with.iter().map(|other| interactions[letter][*other]).sum()
+            };
+            // max_by_key keeps the last of equal scores, so look from the back
+            let best = (0..left.len())
+                .rev()
+                .max_by_key(|index| score(left[*index]))
+                .unwrap();
+            members.push(left.remove(best));
+        }
+    }
+    groups
+}
+
 pub struct LetterSettings {
     num_letters: usize,
 }
 
 impl LetterSettings {
-    pub fn random(rng: &mut ThreadRng) -> Self {
+    pub fn random(rng: &mut SeededRng) -> Self {
         Self {
             num_letters: rng.gen_range(6..=26),
         }
@@ -264,24 +516,124 @@ pub struct SpriteSettings {
 }
 
 impl SpriteSettings {
-    pub fn random() -> Self {
-        let mut rng = rand::thread_rng();
+    pub fn from_seed(seed: u64) -> Self {
+        let mut letter_rng = stream_rng(seed, Stream::Letters);
+        let mut rule_rng = stream_rng(seed, Stream::Rules);
+        let mut color_rng = stream_rng(seed, Stream::Colors);
 
-        let letter_settings = LetterSettings::random(&mut rng);
+        let letter_settings = LetterSettings::random(&mut letter_rng);
         let letters = letter_settings.generate();
 
-        let rule_settings = RuleSettings::random(&mut rng);
-        let rules: Vec<Rule> = rule_settings.generate(&mut rng, &letters);
+        let rule_settings = RuleSettings::random(&mut rule_rng);
+        let rules: Vec<Rule> = rule_settings.generate(&mut rule_rng, &letters);
 
-        let color_settings = ColorSettings::random(&mut rng);
-        let colors = color_settings.generate(&mut rng, &letters);
+        let color_settings = ColorSettings::random(&mut color_rng);
+        let colors = color_settings.generate(&mut color_rng, &letters, &rules);
 
         Self { rules, colors }
     }
 }
 
+// https://en.wikipedia.org/wiki/HSL_and_HSV#HSL_to_RGB
+fn hsl_to_rgb(hue: f32, saturation: f32, lightness: f32) -> [f32; 3] {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let hue_prime = hue / 60.0;
+    let largest_component = chroma * (1.0 - (hue_prime % 2.0 - 1.0).abs());
+    let (r_temp, g_temp, b_temp) = if hue_prime < 1.0 {
+        (chroma, largest_component, 0.0)
+    } else if hue_prime < 2.0 {
+        (largest_component, chroma, 0.0)
+    } else if hue_prime < 3.0 {
+        (0.0, chroma, largest_component)
+    } else if hue_prime < 4.0 {
+        (0.0, largest_component, chroma)
+    } else if hue_prime < 5.0 {
+        (largest_component, 0.0, chroma)
+    } else {
+        (chroma, 0.0, largest_component)
+    };
+    let lightness_match = lightness - chroma / 2.0;
+
+    [
+        r_temp + lightness_match,
+        g_temp + lightness_match,
+        b_temp + lightness_match,
+    ]
+}
+
+// the base hues turned by one random hue, the letters take turns with a little variation
+fn wheel_colors(rng: &mut SeededRng, count: usize, hues: &[f32]) -> Vec<[f32; 3]> {
+    let hue = rng.gen_range(0.0..360.0);
+    (0..count)
+        .map(|index| {
+            hsl_to_rgb(
+                (hue + hues[index % hues.len()] + rng.gen_range(-10.0..10.0)).rem_euclid(360.0),
+                rng.gen_range(0.35..0.9),
+                rng.gen_range(0.25..0.75),
+            )
+        })
+        .collect()
+}
+
+fn to_u8(color: [f32; 3]) -> [u8; 4] {
+    [
+        (color[0].clamp(0.0, 1.0) * 255.0).round() as u8,
+        (color[1].clamp(0.0, 1.0) * 255.0).round() as u8,
+        (color[2].clamp(0.0, 1.0) * 255.0).round() as u8,
+        255,
+    ]
+}
+
+fn lerp3(from: [f32; 3], to: [f32; 3], t: f32) -> [f32; 3] {
+    [
+        from[0] + (to[0] - from[0]) * t,
+        from[1] + (to[1] - from[1]) * t,
+        from[2] + (to[2] - from[2]) * t,
+    ]
+}
+
+// https://bottosson.github.io/posts/oklab/, sRGB channels are gamma encoded in 0..1
+fn srgb_to_oklab(color: [f32; 3]) -> [f32; 3] {
+    let [r, g, b] = color.map(|channel| {
+        if channel <= 0.04045 {
+            channel / 12.92
+        } else {
+            ((channel + 0.055) / 1.055).powf(2.4)
+        }
+    });
+    let l = (0.41222147 * r + 0.53633254 * g + 0.051445993 * b).cbrt();
+    let m = (0.2119035 * r + 0.6806995 * g + 0.10739696 * b).cbrt();
+    let s = (0.08830246 * r + 0.28171884 * g + 0.6299787 * b).cbrt();
+    [
+        0.21045426 * l + 0.7936178 * m - 0.004072047 * s,
+        1.9779985 * l - 2.4285922 * m + 0.4505937 * s,
+        0.025904037 * l + 0.78277177 * m - 0.80867577 * s,
+    ]
+}
+
+// out of gamut colors are clamped to the sRGB cube
+fn oklab_to_srgb(lab: [f32; 3]) -> [f32; 3] {
+    let [lightness, a, b] = lab;
+    let l = (lightness + 0.39633778 * a + 0.21580376 * b).powi(3);
+    let m = (lightness - 0.105561346 * a - 0.06385417 * b).powi(3);
+    let s = (lightness - 0.08948418 * a - 1.2914855 * b).powi(3);
+    [
+        4.0767417 * l - 3.3077116 * m + 0.23096993 * s,
+        -1.268438 * l + 2.6097574 * m - 0.3413194 * s,
+        -0.0041960863 * l - 0.7034186 * m + 1.7076147 * s,
+    ]
+    .map(|channel| {
+        let channel = channel.clamp(0.0, 1.0);
+        if channel <= 0.0031308 {
+            12.92 * channel
+        } else {
+            1.055 * channel.powf(1.0 / 2.4) - 0.055
+        }
+    })
+}
+
 fn weighted_values<T>(
-    rng: &mut ThreadRng,
+    rng: &mut SeededRng,
     values: &[T],
     ratio: f32,
     pref_weight: usize,
@@ -301,7 +653,7 @@ where
 }
 
 fn weighted_index_values<T>(
-    rng: &mut ThreadRng,
+    rng: &mut SeededRng,
     values: &[T],
     indices: &[usize],
     quantity: usize,
@@ -310,9 +662,13 @@ where
     T: Copy,
 {
     let distr = WeightedIndex::new(indices).unwrap();
-    let mut indices: HashSet<usize> = HashSet::new();
+    // Vec instead of HashSet, iteration order must not depend on the hasher
+    let mut indices: Vec<usize> = vec![];
     while indices.len() < quantity {
-        indices.insert(distr.sample(rng));
+        let index = distr.sample(rng);
+        if !indices.contains(&index) {
+            indices.push(index);
+        }
     }
 
     let mut result = vec![];
@@ -321,3 +677,182 @@ where
     }
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn letters_that_meet_in_rules_share_a_group() {
+        let letters: Vec<char> = "ABCDEF".chars().collect();
+        // A and B meet in two rules, C and D in two, E once with F
+        let rules = [
+            ("(?:[AB].*){2}", "5A"),
+            ("....A....", "1B"),
+            ("(?:C.*){3}", "5D"),
+            ("D", "CD[0.5]"),
+            ("E", "5F"),
+        ]
+        .map(|(condition, action)| Rule::try_new(condition, action).unwrap());
+        let interactions = letter_interactions(&rules, &letters);
+        assert_eq!(interactions[0][1], 2);
+        assert_eq!(interactions[2][3], 2);
+        assert_eq!(interactions[4][5], 1);
+        assert_eq!(
+            interactions[0][2] + interactions[1][3] + interactions[0][4],
+            0
+        );
+        // symmetric, a letter does not meet itself
+        for (a, row) in interactions.iter().enumerate() {
+            assert_eq!(row[a], 0);
+            for (b, count) in row.iter().enumerate() {
+                assert_eq!(*count, interactions[b][a]);
+            }
+        }
+
+        let group_of = |groups: &[Vec<usize>], letter: usize| {
+            groups
+                .iter()
+                .position(|group| group.contains(&letter))
+                .unwrap()
+        };
+        let groups = cluster_letters(&interactions, &[2, 2, 2]);
+        assert_eq!(groups.iter().map(Vec::len).collect::<Vec<_>>(), [2, 2, 2]);
+        for (a, b) in [(0, 1), (2, 3), (4, 5)] {
+            assert_eq!(group_of(&groups, a), group_of(&groups, b));
+        }
+        assert_ne!(group_of(&groups, 0), group_of(&groups, 2));
+        assert_ne!(group_of(&groups, 0), group_of(&groups, 4));
+
+        // the bigger group takes both pairs that meet most, the pair that meets once is left
+        let groups = cluster_letters(&interactions, &[2, 4]);
+        assert_eq!(groups[1], [0, 1, 2, 3]);
+        assert_eq!(group_of(&groups, 4), group_of(&groups, 5));
+        assert_ne!(group_of(&groups, 4), group_of(&groups, 0));
+    }
+
+    #[test]
+    fn oklab_round_trips() {
+        for r in (0..=255).step_by(15) {
+            for g in (0..=255).step_by(15) {
+                for b in (0..=255).step_by(15) {
+                    let color = [r, g, b].map(|channel| channel as f32 / 255.0);
+                    let back = oklab_to_srgb(srgb_to_oklab(color));
+                    for (channel, back) in color.iter().zip(back) {
+                        assert!((channel - back).abs() < 1e-3, "{:?} {:?}", color, back);
+                    }
+                }
+            }
+        }
+        // white is the top of the lightness axis and has no chroma
+        let [lightness, a, b] = srgb_to_oklab([1.0, 1.0, 1.0]);
+        assert!((lightness - 1.0).abs() < 1e-3 && a.abs() < 1e-3 && b.abs() < 1e-3);
+
+        // a gradient from a color to itself keeps it
+        let color = [200, 40, 90, 128];
+        let settings = ColorSettings {
+            harmony: Harmony::Gradient {
+                from: color,
+                to: color,
+            },
+            ..ColorSettings::random(&mut stream_rng(0, Stream::Colors))
+        };
+        let colors = settings.generate(&mut stream_rng(0, Stream::Colors), &['A', 'B', 'C'], &[]);
+        assert!(colors.iter().all(|(_, generated)| *generated == color));
+    }
+
+    #[test]
+    fn harmonies_turn_the_hue_by_their_offsets() {
+        let letters: Vec<char> = "ABCDEF".chars().collect();
+        let hues = |harmony: Harmony, seed: u64| -> Vec<f32> {
+            let settings = ColorSettings {
+                harmony,
+                ..ColorSettings::random(&mut stream_rng(seed, Stream::Colors))
+            };
+            settings
+                .generate(&mut stream_rng(seed, Stream::Colors), &letters, &[])
+                .into_iter()
+                .map(|(_, color)| hue(color))
+                .collect()
+        };
+        // rounding to u8 moves a hue by a degree or two, the wheel harmonies add up to 10 each
+        let near = |turn: f32, expected: f32, tolerance: f32| {
+            ((turn - expected + 540.0).rem_euclid(360.0) - 180.0).abs() <= tolerance
+        };
+        let mut oklch_turns = 0;
+        for seed in 0..16 {
+            for (harmony, step) in [(Harmony::Complementary, 180.0), (Harmony::Triadic, 120.0)] {
+                let hues = hues(harmony, seed);
+                let period = (360.0f32 / step).round() as usize;
+                for index in 0..hues.len() - 1 {
+                    let turn = hues[index + 1] - hues[index];
+                    assert!(near(turn, step, 22.0), "{:?} {:?}", harmony, hues);
+                }
+                for index in 0..hues.len() - period {
+                    let turn = hues[index + period] - hues[index];
+                    assert!(near(turn, 0.0, 22.0), "{:?} {:?}", harmony, hues);
+                }
+            }
+
+            // even steps from -spread / 2 to spread / 2 around the random hue
+            let hues_analogous = hues(Harmony::Analogous { spread: 60.0 }, seed);
+            for pair in hues_analogous.windows(2) {
+                assert!(near(pair[1] - pair[0], 12.0, 2.0), "{:?}", hues_analogous);
+            }
+
+            let hues_monochrome = hues(Harmony::Monochrome, seed);
+            for hue in &hues_monochrome {
+                assert!(
+                    near(hue - hues_monochrome[0], 0.0, 3.0),
+                    "{:?}",
+                    hues_monochrome
+                );
+            }
+
+            // the OKLCH ramp turns the hue 60 to 180 degrees in even steps, 12 to 36 each.
+            // colors out of gamut are clamped, which bends their hue, so those are left out
+            let settings = ColorSettings {
+                harmony: Harmony::OkLchRamp,
+                ..ColorSettings::random(&mut stream_rng(seed, Stream::Colors))
+            };
+            let hues_oklch: Vec<Option<f32>> = settings
+                .generate(&mut stream_rng(seed, Stream::Colors), &letters, &[])
+                .into_iter()
+                .map(|(_, color)| {
+                    let rgb = [color[0], color[1], color[2]];
+                    let [_, a, b] = srgb_to_oklab(rgb.map(|channel| channel as f32 / 255.0));
+                    (!rgb.contains(&0) && !rgb.contains(&255)).then(|| b.atan2(a).to_degrees())
+                })
+                .collect();
+            let turns: Vec<f32> = hues_oklch
+                .windows(2)
+                .filter_map(|pair| Some(pair[1]? - pair[0]?))
+                .map(|turn| (turn + 540.0).rem_euclid(360.0) - 180.0)
+                .collect();
+            for turn in &turns {
+                assert!((10.0..=38.0).contains(&turn.abs()), "{:?}", hues_oklch);
+                assert!(near(*turn, turns[0], 4.0), "{:?}", hues_oklch);
+            }
+            oklch_turns += turns.len();
+        }
+        assert!(oklch_turns > 20);
+    }
+
+    // the hue of a color in degrees, 0 for grays
+    fn hue(color: [u8; 4]) -> f32 {
+        let [r, g, b] = [color[0], color[1], color[2]].map(|channel| channel as f32 / 255.0);
+        let max = r.max(g).max(b);
+        let chroma = max - r.min(g).min(b);
+        if chroma == 0.0 {
+            return 0.0;
+        }
+        let hue = if max == r {
+            (g - b) / chroma
+        } else if max == g {
+            (b - r) / chroma + 2.0
+        } else {
+            (r - g) / chroma + 4.0
+        };
+        (hue * 60.0).rem_euclid(360.0)
+    }
+}
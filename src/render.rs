@@ -0,0 +1,196 @@
+use crate::sprite_gen::SpriteGen;
+
+/*
+how update_texture colors a cell. Flat draws the letter color, the other modes draw a
+gradient per letter, from the letter color darkened by depth (0..1) to the letter color
+Neighbors: by how many of the 8 neighbors have the same letter, the inside of a patch gets
+the letter color and its edges and lone cells the dark end
+Age: by the steps since the cell last changed, a new cell starts dark and reaches the letter
+color after steps
+Noise: by the noise the texture was filled from, see noise_fill
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RenderMode {
+    #[default]
+    Flat,
+    Neighbors {
+        depth: f32,
+    },
+    Age {
+        depth: f32,
+        steps: usize,
+    },
+    Noise {
+        depth: f32,
+    },
+}
+
+impl RenderMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RenderMode::Flat => "Flat",
+            RenderMode::Neighbors { .. } => "Neighbors",
+            RenderMode::Age { .. } => "Age",
+            RenderMode::Noise { .. } => "Noise",
+        }
+    }
+
+    pub fn depth(&self) -> f32 {
+        match self {
+            RenderMode::Flat => 0.0,
+            RenderMode::Neighbors { depth }
+            | RenderMode::Age { depth, .. }
+            | RenderMode::Noise { depth } => *depth,
+        }
+    }
+}
+
+// rgba, alpha included. letters without a color are transparent
+pub(crate) fn render(sprite_gen: &SpriteGen, texture: &mut [u8]) {
+    let mode = sprite_gen.render_mode;
+    let depth = mode.depth().clamp(0.0, 1.0);
+    for (index, letter) in sprite_gen.char_texture.get_array().iter().enumerate() {
+        let offset_index = index * 4;
+        let color = *sprite_gen.char_color.get(letter).unwrap_or(&[0, 0, 0, 0]);
+        let color = match mode {
+            RenderMode::Flat => color,
+            _ => shade(color, depth, position(sprite_gen, mode, index)),
+        };
+        texture[offset_index..offset_index + 4].copy_from_slice(&color);
+    }
+}
+
+// where a cell is on its letter's gradient, 0 for the dark end and 1 for the letter color
+fn position(sprite_gen: &SpriteGen, mode: RenderMode, index: usize) -> f32 {
+    let texture = &sprite_gen.char_texture;
+    match mode {
+        RenderMode::Flat => 1.0,
+        RenderMode::Neighbors { .. } => {
+            let letter = texture.pixels[index];
+            let (x, y) = texture.xy_from_index(index);
+            // at the edges Mirror and Clamp resolve neighbors onto the cell itself or onto
+            // other neighbors, every other cell counts once
+            let cells = texture.get_valid_3x3_indices(x, y);
+            let same = cells
+                .iter()
+                .enumerate()
+                .filter(|(index, cell)| match cell {
+                    Some(cell) => {
+                        *cell != (x, y)
+                            && !cells[..*index].contains(&Some(*cell))
+                            && texture.get(cell.0, cell.1) == letter
+                    }
+                    None => false,
+                })
+                .count();
+            same as f32 / 8.0
+        }
+        RenderMode::Age { steps, .. } => {
+            sprite_gen.cell_age(index).min(steps.max(1)) as f32 / steps.max(1) as f32
+        }
+        RenderMode::Noise { .. } => sprite_gen.fill_noise().get(index).copied().unwrap_or(1.0),
+    }
+}
+
+fn shade(color: [u8; 4], depth: f32, position: f32) -> [u8; 4] {
+    let factor = 1.0 - depth * (1.0 - position.clamp(0.0, 1.0));
+    [
+        (color[0] as f32 * factor).round() as u8,
+        (color[1] as f32 * factor).round() as u8,
+        (color[2] as f32 * factor).round() as u8,
+        color[3],
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::char_texture::Boundary;
+
+    const COLOR: [u8; 4] = [160, 80, 240, 200];
+
+    fn sprite_gen(letters: &str, width: usize, mode: RenderMode) -> SpriteGen {
+        let mut sprite_gen = SpriteGen::new(width, letters.len() / width);
+        sprite_gen.char_texture.pixels = letters.chars().collect();
+        sprite_gen.char_color = [('A', COLOR), ('B', [10, 20, 30, 255])].into();
+        sprite_gen.render_mode = mode;
+        sprite_gen
+    }
+
+    fn pixels(sprite_gen: &SpriteGen) -> Vec<[u8; 4]> {
+        let mut texture = vec![0; sprite_gen.char_texture.pixels.len() * 4];
+        sprite_gen.update_texture(&mut texture);
+        texture
+            .chunks(4)
+            .map(|pixel| pixel.try_into().unwrap())
+            .collect()
+    }
+
+    // COLOR at position 3/8 of its gradient with depth 1, alpha kept
+    const THREE_EIGHTHS: [u8; 4] = [60, 30, 90, 200];
+
+    #[test]
+    fn flat_draws_the_letter_color() {
+        let sprite_gen = sprite_gen("AB#", 3, RenderMode::Flat);
+        assert_eq!(
+            pixels(&sprite_gen),
+            [COLOR, [10, 20, 30, 255], [0, 0, 0, 0]]
+        );
+    }
+
+    #[test]
+    fn neighbors_count_each_cell_once() {
+        for (boundary, corner) in [
+            (Boundary::Fill, THREE_EIGHTHS),
+            (Boundary::Mirror, THREE_EIGHTHS),
+            (Boundary::Clamp, THREE_EIGHTHS),
+            // all 8 other cells are around the corner
+            (Boundary::Wrap, COLOR),
+        ] {
+            let mut sprite_gen = sprite_gen("AAAAAAAAA", 3, RenderMode::Neighbors { depth: 1.0 });
+            sprite_gen.char_texture.boundary = boundary;
+            let pixels = pixels(&sprite_gen);
+            assert_eq!(pixels[0], corner, "{:?}", boundary);
+            assert_eq!(pixels[4], COLOR, "{:?}", boundary);
+        }
+
+        // only the same letter counts, B has no B neighbor
+        let sprite_gen = sprite_gen("AAAABAAAA", 3, RenderMode::Neighbors { depth: 1.0 });
+        let pixels = pixels(&sprite_gen);
+        assert_eq!(pixels[4], [0, 0, 0, 255]);
+        assert_eq!(pixels[1], [80, 40, 120, 200]);
+    }
+
+    #[test]
+    fn age_brightens_cells_that_stay() {
+        let mode = RenderMode::Age {
+            depth: 1.0,
+            steps: 4,
+        };
+        let mut sprite_gen = sprite_gen("AB", 2, mode);
+        sprite_gen.rules.clear();
+        sprite_gen.apply();
+        assert_eq!(pixels(&sprite_gen)[0], [40, 20, 60, 200]);
+        // painting starts the cell over
+        sprite_gen.char_texture.set(1, 0, 'A');
+        sprite_gen.apply();
+        assert_eq!(pixels(&sprite_gen), [[80, 40, 120, 200], [40, 20, 60, 200]]);
+        // past steps the letter color is reached
+        for _ in 0..4 {
+            sprite_gen.apply();
+        }
+        assert_eq!(pixels(&sprite_gen), [COLOR, COLOR]);
+    }
+
+    #[test]
+    fn noise_follows_the_fill_noise() {
+        let mut sprite_gen = sprite_gen("AAA", 3, RenderMode::Noise { depth: 1.0 });
+        // not filled at this size, drawn as the letter color
+        assert_eq!(pixels(&sprite_gen), [COLOR; 3]);
+        sprite_gen.set_fill_noise(vec![0.0, 0.375, 1.0]);
+        assert_eq!(pixels(&sprite_gen), [[0, 0, 0, 200], THREE_EIGHTHS, COLOR]);
+        // half the depth goes half way to black
+        sprite_gen.render_mode = RenderMode::Noise { depth: 0.5 };
+        assert_eq!(pixels(&sprite_gen)[0], [80, 40, 120, 200]);
+    }
+}
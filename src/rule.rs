@@ -1,7 +1,11 @@
-use lazy_static::lazy_static;
+use std::sync::Arc;
+
 use regex::Regex;
 
-#[derive(Debug, Clone)]
+use crate::char_texture::CharTexture;
+use crate::neighborhood::{Neighborhood, NeighborhoodError};
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Action {
     pub chance: Option<f32>,
     pub location: ActionParam,
@@ -13,7 +17,7 @@ pub struct Actions {
     pub actions: Vec<Action>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ActionParam {
     Char(char),
     Index(usize),
@@ -23,91 +27,613 @@ pub enum ActionParam {
 #[derive(Debug, Clone)]
 pub struct Rule {
     action: Vec<Action>,
-    condition: Regex,
+    compiled: Option<CompiledCondition>,
+    condition: Option<Regex>,
+    error: Option<RuleError>,
+    neighborhood: Neighborhood,
+    // shared with the inputs of a step, so steps don't copy them
+    offsets: Arc<[(i32, i32)]>,
     original_action: String,
     original_condition: String,
 }
 
+#[derive(Debug, Clone)]
+pub enum RuleError {
+    Condition(regex::Error),
+    Neighborhood(NeighborhoodError),
+    // position is the index of the offending character in the action
+    Action {
+        position: usize,
+        expected: &'static str,
+        found: Option<char>,
+    },
+}
+
+impl std::fmt::Display for RuleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuleError::Condition(error) => write!(f, "invalid condition: {}", error),
+            RuleError::Neighborhood(error) => write!(f, "invalid neighborhood: {}", error),
+            RuleError::Action {
+                position,
+                expected,
+                found: Some(found),
+            } => write!(
+                f,
+                "expected {} at position {}, found '{}'",
+                expected,
+                position + 1,
+                found
+            ),
+            RuleError::Action {
+                position,
+                expected,
+                found: None,
+            } => write!(
+                f,
+                "expected {} at position {}, found end of action",
+                expected,
+                position + 1
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RuleError {}
+
 impl Rule {
+    // an invalid condition or action is kept as typed but disables the rule, see error()
     pub fn new(condition: &str, action: &str) -> Self {
-        Self {
-            action: Rule::parse_action(action),
-            condition: Rule::parse_condition(condition),
+        let mut rule = Self {
+            action: vec![],
+            compiled: None,
+            condition: None,
+            error: None,
+            offsets: Arc::from(vec![]),
+            neighborhood: Neighborhood::default(),
             original_action: action.to_owned(),
             original_condition: condition.to_owned(),
+        };
+        rule.parse();
+        rule
+    }
+
+    pub fn try_new(condition: &str, action: &str) -> Result<Self, RuleError> {
+        let rule = Rule::new(condition, action);
+        match rule.error {
+            Some(error) => Err(error),
+            None => Ok(rule),
         }
     }
 
+    pub fn with_neighborhood(mut self, neighborhood: Neighborhood) -> Self {
+        self.set_neighborhood(neighborhood);
+        self
+    }
+
     pub fn action(&self) -> &[Action] {
         self.action.as_ref()
     }
 
-    pub fn condition(&self) -> &Regex {
-        &self.condition
+    // None while the rule is disabled, which is when the condition is empty or the rule is invalid
+    pub fn condition(&self) -> Option<&Regex> {
+        self.condition.as_ref()
+    }
+
+    // set when the condition has a shape that is checked without the regex
+    pub fn compiled(&self) -> Option<&CompiledCondition> {
+        self.compiled.as_ref()
+    }
+
+    pub fn error(&self) -> Option<&RuleError> {
+        self.error.as_ref()
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.error.is_none()
+    }
+
+    // a chance or a wildcard value, the same neighborhood can give different writes
+    pub fn is_random(&self) -> bool {
+        self.action
+            .iter()
+            .any(|action| action.chance.is_some() || matches!(action.value, ActionParam::Wildcard))
+    }
+
+    pub fn neighborhood(&self) -> &Neighborhood {
+        &self.neighborhood
+    }
+
+    // offsets of the neighborhood, the condition is matched against these cells in order.
+    // empty while the neighborhood is out of range
+    pub fn offsets(&self) -> &[(i32, i32)] {
+        &self.offsets
+    }
+
+    pub(crate) fn shared_offsets(&self) -> Arc<[(i32, i32)]> {
+        self.offsets.clone()
+    }
+
+    // a neighborhood out of range disables the rule like an invalid condition
+    pub fn set_neighborhood(&mut self, neighborhood: Neighborhood) {
+        self.neighborhood = neighborhood;
+        self.parse();
     }
 
     pub fn set_action(&mut self, action: &str) {
         self.original_action = action.to_owned();
-        self.action = Rule::parse_action(action);
+        self.parse();
     }
 
     pub fn set_condition(&mut self, condition: &str) {
         self.original_condition = condition.to_owned();
-        self.condition = Rule::parse_condition(condition);
+        self.parse();
     }
 
-    fn parse_condition(condition: &str) -> Regex {
-        if let Ok(new_regex) = Regex::new(condition) {
-            new_regex
-        } else {
-            Regex::new("").unwrap()
+    fn parse(&mut self) {
+        self.action = vec![];
+        self.compiled = None;
+        self.condition = None;
+        self.error = None;
+
+        self.offsets = match self.neighborhood.offsets() {
+            Ok(offsets) => offsets.into(),
+            Err(error) => {
+                self.offsets = Arc::from(vec![]);
+                self.error = Some(RuleError::Neighborhood(error));
+                return;
+            }
+        };
+        let condition = match Regex::new(&self.original_condition) {
+            Ok(condition) => condition,
+            Err(error) => {
+                self.error = Some(RuleError::Condition(error));
+                return;
+            }
+        };
+        let action = match ActionParser::new(&self.original_action).actions() {
+            Ok(action) => action,
+            Err(error) => {
+                self.error = Some(error);
+                return;
+            }
+        };
+        if !self.original_condition.is_empty() {
+            self.condition = Some(condition);
         }
+        self.action = action;
+        self.compile();
     }
 
-    fn parse_action(action: &str) -> Vec<Action> {
-        lazy_static! {
-            static ref PARSE_ACTION: Regex =
-                Regex::new(r"([A-Z1-9*])([A-Z1-9*])(?:\[([0]?[.][0-9]+)\])?").unwrap();
-        }
-        let mut results = vec![];
-        for caps in PARSE_ACTION.captures_iter(action) {
-            let chance = caps
-                .get(3)
-                .and_then(|c| Some(c.as_str().parse::<f32>().ok()).flatten());
-            let location = caps[1].chars().next().unwrap();
-            let value = caps[2].chars().next().unwrap();
-
-            let final_location;
-            if location.is_ascii_digit() {
-                final_location = ActionParam::Index(location.to_digit(10).unwrap() as usize);
-            } else if value == '*' {
-                final_location = ActionParam::Wildcard;
+    // positional patterns depend on the neighborhood size, so this reruns when it changes
+    fn compile(&mut self) {
+        self.compiled = self
+            .condition
+            .as_ref()
+            .and_then(|_| CompiledCondition::compile(&self.original_condition, self.offsets.len()));
+    }
+
+    pub fn original_action(&self) -> &str {
+        self.original_action.as_ref()
+    }
+
+    pub fn original_condition(&self) -> &str {
+        self.original_condition.as_ref()
+    }
+}
+
+/*
+the two condition shapes the rule generator makes, matched against the letters directly
+Positional: "..A.[BC]...", one letter, class or '.' per cell of the neighborhood. only
+    compiled when the pattern is as long as the neighborhood, otherwise the unanchored
+    regex could match at other offsets
+Count: "(?:[AB].*){2}", at least 2 cells are one of the letters, in any position
+classes may only list letters and FILL_CHAR, anything else is left to the regex
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompiledCondition {
+    // (cell, allowed letters) for every cell that is not '.'
+    Positional(Vec<(usize, LetterSet)>),
+    Count { letters: LetterSet, count: usize },
+}
+
+impl CompiledCondition {
+    pub fn compile(condition: &str, size: usize) -> Option<Self> {
+        CompiledCondition::compile_count(condition)
+            .or_else(|| CompiledCondition::compile_positional(condition, size))
+    }
+
+    fn compile_count(condition: &str) -> Option<Self> {
+        let (letters, rest) = LetterSet::parse(condition.strip_prefix("(?:")?)?;
+        let count = rest.strip_prefix(".*){")?.strip_suffix('}')?.parse().ok()?;
+        Some(CompiledCondition::Count { letters, count })
+    }
+
+    fn compile_positional(condition: &str, size: usize) -> Option<Self> {
+        let mut cells = vec![];
+        let mut cell = 0;
+        let mut rest = condition;
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('.') {
+                rest = after;
             } else {
-                final_location = ActionParam::Char(location);
+                let (letters, after) = LetterSet::parse(rest)?;
+                cells.push((cell, letters));
+                rest = after;
             }
+            cell += 1;
+        }
+        (cell == size).then_some(CompiledCondition::Positional(cells))
+    }
 
-            let final_value;
-            if value.is_ascii_digit() {
-                final_value = ActionParam::Index(value.to_digit(10).unwrap() as usize);
-            } else if value == '*' {
-                final_value = ActionParam::Wildcard;
-            } else {
-                final_value = ActionParam::Char(value);
+    // same result as the regex on the stringified neighborhood of (x, y)
+    pub fn matches(
+        &self,
+        texture: &CharTexture,
+        x: usize,
+        y: usize,
+        offsets: &[(i32, i32)],
+    ) -> bool {
+        match self {
+            CompiledCondition::Positional(cells) => cells
+                .iter()
+                .all(|(cell, letters)| letters.contains(texture.neighbor(x, y, offsets[*cell]))),
+            CompiledCondition::Count { letters, count } => {
+                offsets
+                    .iter()
+                    .filter(|offset| letters.contains(texture.neighbor(x, y, **offset)))
+                    .take(*count)
+                    .count()
+                    == *count
+            }
+        }
+    }
+}
+
+// A-Z and FILL_CHAR as bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LetterSet(u32);
+
+impl LetterSet {
+    const FILL_BIT: u32 = 1 << 26;
+
+    fn bit(letter: char) -> Option<u32> {
+        match letter {
+            'A'..='Z' => Some(1 << (letter as u32 - 'A' as u32)),
+            CharTexture::FILL_CHAR => Some(LetterSet::FILL_BIT),
+            _ => None,
+        }
+    }
+
+    pub fn contains(self, letter: char) -> bool {
+        matches!(LetterSet::bit(letter), Some(bit) if self.0 & bit != 0)
+    }
+
+    // a single letter or a class like [AB] at the start of pattern, and the rest of it
+    fn parse(pattern: &str) -> Option<(LetterSet, &str)> {
+        let (letters, rest) = match pattern.strip_prefix('[') {
+            Some(class) => {
+                let end = class.find(']')?;
+                (&class[..end], &class[end + 1..])
             }
+            None => pattern.split_at(pattern.chars().next()?.len_utf8()),
+        };
+        if letters.is_empty() {
+            return None;
+        }
+        let mut set = LetterSet::default();
+        for letter in letters.chars() {
+            set.0 |= LetterSet::bit(letter)?;
+        }
+        Some((set, rest))
+    }
+}
+
+/*
+version 1 configs were read by a regex that skipped anything it could not parse, this keeps
+their meaning in the current syntax:
+- a * value made the location a wildcard too, A* is **
+- a * location with a letter value was a letter that never matches, *A is dropped
+- an empty or invalid condition matched every neighborhood, it becomes .
+*/
+pub(crate) fn migrate_legacy_rule(condition: &str, action: &str) -> (String, String) {
+    let condition = match Regex::new(condition) {
+        Ok(_) if !condition.is_empty() => condition.to_string(),
+        _ => ".".to_string(),
+    };
+    let legacy_action =
+        Regex::new(r"([A-Z1-9*]|\[[1-9][0-9]*\])([A-Z1-9*]|\[[1-9][0-9]*\])(\[[0]?[.][0-9]+\])?")
+            .unwrap();
+    let mut migrated = String::new();
+    for caps in legacy_action.captures_iter(action) {
+        let (location, value) = (&caps[1], &caps[2]);
+        let location = match location.chars().next() {
+            Some('[' | '1'..='9') => location,
+            _ if value == "*" => "*",
+            Some('*') => continue,
+            _ => location,
+        };
+        migrated.push_str(location);
+        migrated.push_str(value);
+        migrated.push_str(caps.get(3).map_or("", |chance| chance.as_str()));
+    }
+    (condition, migrated)
+}
+
+/* <location><value>[<chance>], repeated, whitespace between actions is ignored
+<location> and <value> are one of A-Z, 1-9, * or an index in brackets like [13]
+<chance> is [.n] or [0.n]
+*/
+struct ActionParser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl ActionParser {
+    fn new(action: &str) -> Self {
+        Self {
+            chars: action.chars().collect(),
+            position: 0,
+        }
+    }
+
+    fn actions(mut self) -> Result<Vec<Action>, RuleError> {
+        let mut results = vec![];
+        self.skip_whitespace();
+        while self.peek().is_some() {
+            let location = self.param("a location (A-Z, 1-9, * or [n])")?;
+            let value = self.param("a value (A-Z, 1-9, * or [n])")?;
+            let chance = self.chance()?;
             results.push(Action {
                 chance,
-                location: final_location,
-                value: final_value,
-            })
+                location,
+                value,
+            });
+            self.skip_whitespace();
         }
-        results
+        Ok(results)
     }
 
-    pub fn original_action(&self) -> &str {
-        self.original_action.as_ref()
+    fn param(&mut self, expected: &'static str) -> Result<ActionParam, RuleError> {
+        let param = match self.peek() {
+            Some(c @ 'A'..='Z') => ActionParam::Char(c),
+            Some(c @ '1'..='9') => ActionParam::Index(c.to_digit(10).unwrap() as usize),
+            Some('*') => ActionParam::Wildcard,
+            Some('[') => {
+                self.position += 1;
+                return self.index();
+            }
+            _ => return Err(self.error(expected)),
+        };
+        self.position += 1;
+        Ok(param)
     }
 
-    pub fn original_condition(&self) -> &str {
-        self.original_condition.as_ref()
+    // after the '[' of a bracketed index
+    fn index(&mut self) -> Result<ActionParam, RuleError> {
+        if !matches!(self.peek(), Some('1'..='9')) {
+            return Err(self.error("an index starting with 1-9"));
+        }
+        let mut index = 0usize;
+        while let Some(digit) = self.peek().and_then(|c| c.to_digit(10)) {
+            index = index.saturating_mul(10).saturating_add(digit as usize);
+            self.position += 1;
+        }
+        self.expect(']', "']'")?;
+        Ok(ActionParam::Index(index))
+    }
+
+    // a '[' after the value starts a chance if it is followed by '.' or '0',
+    // otherwise it is the bracketed location of the next action
+    fn chance(&mut self) -> Result<Option<f32>, RuleError> {
+        if self.peek() != Some('[') || !matches!(self.peek_at(1), Some('.' | '0')) {
+            return Ok(None);
+        }
+        self.position += 1;
+        let start = self.position;
+        if self.peek() == Some('0') {
+            self.position += 1;
+        }
+        self.expect('.', "'.'")?;
+        if !matches!(self.peek(), Some('0'..='9')) {
+            return Err(self.error("a digit"));
+        }
+        while matches!(self.peek(), Some('0'..='9')) {
+            self.position += 1;
+        }
+        let chance: String = self.chars[start..self.position].iter().collect();
+        self.expect(']', "']'")?;
+        Ok(chance.parse().ok())
+    }
+
+    fn expect(&mut self, c: char, expected: &'static str) -> Result<(), RuleError> {
+        if self.peek() != Some(c) {
+            return Err(self.error(expected));
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(c) if c.is_whitespace()) {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.position + offset).copied()
+    }
+
+    fn error(&self, expected: &'static str) -> RuleError {
+        RuleError::Action {
+            position: self.position,
+            expected,
+            found: self.peek(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn action(location: ActionParam, value: ActionParam, chance: Option<f32>) -> Action {
+        Action {
+            chance,
+            location,
+            value,
+        }
+    }
+
+    #[test]
+    fn parses_every_action_form() {
+        use ActionParam::*;
+        let cases = [
+            ("5B", vec![action(Index(5), Char('B'), None)]),
+            ("AB", vec![action(Char('A'), Char('B'), None)]),
+            ("*B", vec![action(Wildcard, Char('B'), None)]),
+            ("A*", vec![action(Char('A'), Wildcard, None)]),
+            ("53", vec![action(Index(5), Index(3), None)]),
+            ("[13][25]", vec![action(Index(13), Index(25), None)]),
+            ("5B[0.05]", vec![action(Index(5), Char('B'), Some(0.05))]),
+            ("5B[.5]", vec![action(Index(5), Char('B'), Some(0.5))]),
+            (
+                "5B[13]C",
+                vec![
+                    action(Index(5), Char('B'), None),
+                    action(Index(13), Char('C'), None),
+                ],
+            ),
+            (
+                " 5B  AC[0.5] ",
+                vec![
+                    action(Index(5), Char('B'), None),
+                    action(Char('A'), Char('C'), Some(0.5)),
+                ],
+            ),
+            ("", vec![]),
+        ];
+        for (text, expected) in cases {
+            let rule = Rule::try_new(".", text).unwrap();
+            assert_eq!(rule.action(), expected.as_slice(), "{:?}", text);
+        }
+
+        for (text, position) in [
+            ("5", 1),
+            ("5b", 1),
+            ("0B", 0),
+            ("[0]B", 1),
+            ("[5B", 2),
+            ("5B[0.]", 5),
+            ("5B[0.5", 6),
+        ] {
+            match Rule::try_new(".", text) {
+                Err(RuleError::Action { position: at, .. }) => {
+                    assert_eq!(at, position, "{:?}", text)
+                }
+                other => panic!("{:?} parsed as {:?}", text, other.map(|rule| rule.action)),
+            }
+        }
+        assert!(matches!(
+            Rule::try_new("[A", "5B"),
+            Err(RuleError::Condition(_))
+        ));
+        assert!(Rule::new("", "5B").condition().is_none());
+    }
+
+    #[test]
+    fn legacy_rules_keep_their_meaning() {
+        for ((condition, action), expected) in [
+            (("A", "5B"), ("A", "5B")),
+            (("A", "A*"), ("A", "**")),
+            (("A", "5*"), ("A", "5*")),
+            (("A", "**"), ("A", "**")),
+            (("A", "*B5C"), ("A", "5C")),
+            (("A", "5B[0.5] x [13]C"), ("A", "5B[0.5][13]C")),
+            (("", "5B"), (".", "5B")),
+            (("[A", "5B"), (".", "5B")),
+        ] {
+            let (condition, action) = migrate_legacy_rule(condition, action);
+            assert_eq!((condition.as_str(), action.as_str()), expected);
+            assert!(Rule::try_new(&condition, &action).is_ok());
+        }
+    }
+
+    // a random positional pattern with one '.', letter or class per cell
+    fn positional_condition(rng: &mut crate::seed::SeededRng, size: usize) -> String {
+        use rand::Rng;
+        (0..size)
+            .map(|_| match rng.gen_range(0..6) {
+                0 => "A",
+                1 => "#",
+                2 => "[BC]",
+                3 => "[A#D]",
+                _ => ".",
+            })
+            .collect()
+    }
+
+    #[test]
+    fn compiled_conditions_match_like_the_regex() {
+        use crate::char_texture::Boundary;
+        use crate::seed::{stream_rng, Stream};
+        use rand::seq::SliceRandom;
+
+        let mut rng = stream_rng(11, Stream::Rules);
+        let neighborhoods = [
+            Neighborhood::Moore(1),
+            Neighborhood::Moore(2),
+            Neighborhood::VonNeumann(1),
+            Neighborhood::Hexagonal,
+            Neighborhood::Custom(vec![(0, 2), (-1, 0), (0, 0), (3, -1)]),
+        ];
+        let boundaries = [
+            Boundary::Fill,
+            Boundary::Wrap,
+            Boundary::Mirror,
+            Boundary::Clamp,
+        ];
+        for neighborhood in neighborhoods {
+            let offsets = neighborhood.offsets().unwrap();
+            let mut conditions: Vec<String> =
+                ["(?:A.*){2}", "(?:[BC].*){3}", "(?:[#D].*){1}", "(?:A.*){0}"]
+                    .iter()
+                    .map(|condition| condition.to_string())
+                    .collect();
+            conditions.push(".".repeat(offsets.len()));
+            conditions.extend((0..20).map(|_| positional_condition(&mut rng, offsets.len())));
+
+            for condition in conditions {
+                let compiled = CompiledCondition::compile(&condition, offsets.len())
+                    .unwrap_or_else(|| panic!("{:?} did not compile", condition));
+                let regex = Regex::new(&condition).unwrap();
+                for boundary in boundaries {
+                    let mut texture = CharTexture::new(9, 7);
+                    texture.boundary = boundary;
+                    for letter in texture.pixels.iter_mut() {
+                        *letter = *['A', 'B', 'C', 'D'].choose(&mut rng).unwrap();
+                    }
+                    for index in 0..texture.pixels.len() {
+                        let (x, y) = texture.xy_from_index(index);
+                        let neighbors: String = texture
+                            .stringify_offsets(x, y, &offsets)
+                            .into_iter()
+                            .collect();
+                        assert_eq!(
+                            compiled.matches(&texture, x, y, &offsets),
+                            regex.is_match(&neighbors),
+                            "{:?} on {:?} with {:?}",
+                            condition,
+                            neighbors,
+                            boundary
+                        );
+                    }
+                }
+            }
+        }
     }
 }
@@ -1,51 +1,623 @@
-use std::collections::BTreeMap;
+use std::{
+    collections::BTreeMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use crate::rule::Rule;
+use serde::{Deserialize, Serialize};
 
-pub fn texture_to_png_base64(data: Vec<u8>, width: usize, height: usize) -> String {
+use crate::char_texture::Boundary;
+use crate::neighborhood::{Neighborhood, NeighborhoodError};
+use crate::rule::{migrate_legacy_rule, Rule};
+use crate::sprite_gen::{ApplyMode, SpriteGen};
+
+// the generating config is stored as an iTXt chunk under this keyword
+pub const PNG_CONFIG_KEYWORD: &str = "auto-config";
+const PNG_DATA_URL_PREFIX: &str = "data:image/png;base64,";
+pub(crate) const PNG_SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+pub fn texture_to_png(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    config: &Config,
+) -> Result<Vec<u8>, png::EncodingError> {
     let mut output: Vec<u8> = Vec::new();
-    let mut cursor = std::io::Cursor::new(&mut output);
-    if image::write_buffer_with_format(
-        &mut cursor,
-        &data,
+    let mut encoder = png::Encoder::new(
+        &mut output,
         width.try_into().unwrap(),
         height.try_into().unwrap(),
-        image::ColorType::Rgba8,
-        image::ImageFormat::Png,
+    );
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.add_itxt_chunk(PNG_CONFIG_KEYWORD.to_string(), serialize_config(config))?;
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(data)?;
+    writer.finish()?;
+    Ok(output)
+}
+
+pub fn texture_to_png_base64(
+    data: Vec<u8>,
+    width: usize,
+    height: usize,
+    config: &Config,
+) -> Result<String, png::EncodingError> {
+    let output = texture_to_png(&data, width, height, config)?;
+    Ok(format!("{}{}", PNG_DATA_URL_PREFIX, base64::encode(output)))
+}
+
+// recover the config embedded by texture_to_png
+pub fn config_from_png(data: &[u8]) -> Result<Config, ConfigError> {
+    let reader = png::Decoder::new(data).read_info()?;
+    let info = reader.info();
+    for chunk in info.utf8_text.iter() {
+        if chunk.keyword == PNG_CONFIG_KEYWORD {
+            return deserialize_config(&chunk.get_text()?);
+        }
+    }
+    for chunk in info.uncompressed_latin1_text.iter() {
+        if chunk.keyword == PNG_CONFIG_KEYWORD {
+            return deserialize_config(&chunk.text);
+        }
+    }
+    Err(ConfigError::NoEmbeddedConfig)
+}
+
+// config file contents, either JSON or a png exported by this tool
+pub fn deserialize_config_bytes(data: &[u8]) -> Result<Config, ConfigError> {
+    if data.starts_with(&PNG_SIGNATURE) {
+        config_from_png(data)
+    } else {
+        deserialize_config(std::str::from_utf8(data)?)
+    }
+}
+
+/*
+config format history
+1: anonymous tuple [[[condition, action], ...], [[letter, [r, g, b, a]], ...], seed?]
+2: named fields, see Config
+*/
+pub const CONFIG_VERSION: u32 = 2;
+
+// larger configs are rejected, a crafted config or png would otherwise allocate without bound.
+// neighborhoods are limited the same way, see MAX_RADIUS
+pub const MAX_DIMENSION: usize = 16384;
+pub const MAX_CELLS: usize = 4096 * 4096;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Config {
+    pub version: u32,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub author: String,
+    pub rules: Vec<RuleConfig>,
+    pub palette: Vec<PaletteEntry>,
+    #[serde(default)]
+    pub dimensions: Option<Dimensions>,
+    #[serde(default)]
+    pub seed: Option<u64>,
+    // number of rule applications after the noise fill
+    #[serde(default)]
+    pub steps: usize,
+    #[serde(default)]
+    pub apply_mode: ApplyMode,
+    #[serde(default)]
+    pub boundary: Boundary,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuleConfig {
+    pub condition: String,
+    pub action: String,
+    #[serde(default)]
+    pub neighborhood: Neighborhood,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PaletteEntry {
+    pub letter: char,
+    pub color: [u8; 4],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Dimensions {
+    pub width: usize,
+    pub height: usize,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Json(serde_json::Error),
+    UnsupportedVersion(u32),
+    UnknownFormat,
+    EmptyPalette,
+    InvalidDimensions(Dimensions),
+    TooLarge(Dimensions),
+    Neighborhood(NeighborhoodError),
+    Utf8(std::str::Utf8Error),
+    Base64(base64::DecodeError),
+    Png(png::DecodingError),
+    NoEmbeddedConfig,
+    Io(io::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Json(e) => write!(f, "invalid config: {}", e),
+            ConfigError::UnsupportedVersion(version) => write!(
+                f,
+                "config version {} is newer than the supported version {}",
+                version, CONFIG_VERSION
+            ),
+            ConfigError::UnknownFormat => write!(
+                f,
+                "config must be a versioned object or a legacy [rules, colors] array"
+            ),
+            ConfigError::EmptyPalette => write!(f, "config palette has no letters"),
+            ConfigError::InvalidDimensions(dimensions) => write!(
+                f,
+                "config dimensions {}x{} must be at least 1x1",
+                dimensions.width, dimensions.height
+            ),
+            ConfigError::TooLarge(dimensions) => write!(
+                f,
+                "config dimensions {}x{} are too large, the limit is {} per side and {} cells",
+                dimensions.width, dimensions.height, MAX_DIMENSION, MAX_CELLS
+            ),
+            ConfigError::Neighborhood(e) => write!(f, "invalid config: {}", e),
+            ConfigError::Utf8(e) => write!(f, "config is not valid text: {}", e),
+            ConfigError::Base64(e) => write!(f, "invalid png data url: {}", e),
+            ConfigError::Png(e) => write!(f, "invalid png: {}", e),
+            ConfigError::NoEmbeddedConfig => write!(f, "png does not contain a config"),
+            ConfigError::Io(e) => write!(f, "could not read config: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<serde_json::Error> for ConfigError {
+    fn from(e: serde_json::Error) -> Self {
+        ConfigError::Json(e)
+    }
+}
+
+impl From<std::str::Utf8Error> for ConfigError {
+    fn from(e: std::str::Utf8Error) -> Self {
+        ConfigError::Utf8(e)
+    }
+}
+
+impl From<base64::DecodeError> for ConfigError {
+    fn from(e: base64::DecodeError) -> Self {
+        ConfigError::Base64(e)
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+impl From<png::DecodingError> for ConfigError {
+    fn from(e: png::DecodingError) -> Self {
+        ConfigError::Png(e)
+    }
+}
+
+impl Config {
+    pub fn from_sprite_gen(sprite_gen: &SpriteGen) -> Self {
+        let (width, height) = sprite_gen.char_texture.dimensions;
+        Self {
+            version: CONFIG_VERSION,
+            name: String::new(),
+            author: String::new(),
+            rules: sprite_gen
+                .rules
+                .iter()
+                .map(|rule| RuleConfig {
+                    condition: rule.original_condition().to_string(),
+                    action: rule.original_action().to_string(),
+                    neighborhood: rule.neighborhood().clone(),
+                })
+                .collect(),
+            palette: sprite_gen
+                .char_color
+                .iter()
+                .map(|(letter, color)| PaletteEntry {
+                    letter: *letter,
+                    color: *color,
+                })
+                .collect(),
+            dimensions: Some(Dimensions { width, height }),
+            seed: Some(sprite_gen.seed()),
+            steps: sprite_gen.steps(),
+            apply_mode: sprite_gen.apply_mode,
+            boundary: sprite_gen.char_texture.boundary,
+        }
+    }
+
+    pub fn rules(&self) -> Vec<Rule> {
+        self.rules
+            .iter()
+            .map(|rule| {
+                Rule::new(&rule.condition, &rule.action)
+                    .with_neighborhood(rule.neighborhood.clone())
+            })
+            .collect()
+    }
+
+    pub fn colors(&self) -> BTreeMap<char, [u8; 4]> {
+        self.palette
+            .iter()
+            .map(|entry| (entry.letter, entry.color))
+            .collect()
+    }
+
+    // replace rules and colors, and seed/dimensions when the config has them.
+    // the texture is not refilled, callers decide whether to noise_fill
+    pub fn load_into(&self, sprite_gen: &mut SpriteGen) {
+        sprite_gen.rules = self.rules();
+        sprite_gen.char_color = self.colors();
+        sprite_gen.apply_mode = self.apply_mode;
+        sprite_gen.char_texture.boundary = self.boundary;
+        if let Some(seed) = self.seed {
+            sprite_gen.set_seed(seed);
+        }
+        if let Some(dimensions) = self.dimensions {
+            sprite_gen
+                .char_texture
+                .resize(dimensions.width, dimensions.height);
+        }
+    }
+
+    fn validate(self) -> Result<Self, ConfigError> {
+        if self.version > CONFIG_VERSION {
+            return Err(ConfigError::UnsupportedVersion(self.version));
+        }
+        if self.palette.is_empty() {
+            return Err(ConfigError::EmptyPalette);
+        }
+        if let Some(dimensions) = self.dimensions {
+            if dimensions.width < 1 || dimensions.height < 1 {
+                return Err(ConfigError::InvalidDimensions(dimensions));
+            }
+            if dimensions.width > MAX_DIMENSION
+                || dimensions.height > MAX_DIMENSION
+                || dimensions.width * dimensions.height > MAX_CELLS
+            {
+                return Err(ConfigError::TooLarge(dimensions));
+            }
+        }
+        for rule in &self.rules {
+            rule.neighborhood
+                .check()
+                .map_err(ConfigError::Neighborhood)?;
+        }
+        Ok(self)
+    }
+}
+
+// a json config or a png exported by this tool
+pub fn load_config_file(path: &Path) -> Result<Config, ConfigError> {
+    deserialize_config_bytes(&fs::read(path)?)
+}
+
+// pretty printed, config files are meant to be kept and edited
+pub fn save_config_file(path: &Path, config: &Config) -> io::Result<()> {
+    let json = serde_json::to_string_pretty(config).map_err(io::Error::from)?;
+    fs::write(path, json)
+}
+
+/*
+files saved by the desktop app, <name>-<utc timestamp>.<extension> in the output directory
+with the config that made it next to it under the same name as json. a number is added
+when the name is taken. not for the browser, there is no clock or filesystem there
+*/
+pub fn write_export(
+    directory: &Path,
+    extension: &str,
+    data: &[u8],
+    config: &Config,
+) -> io::Result<PathBuf> {
+    let mut paths = write_export_files(directory, &[(format!(".{}", extension), data)], config)?;
+    Ok(paths.remove(0))
+}
+
+// several files of one export, <name>-<utc timestamp><suffix> each, with one config
+pub fn write_export_files(
+    directory: &Path,
+    files: &[(String, &[u8])],
+    config: &Config,
+) -> io::Result<Vec<PathBuf>> {
+    fs::create_dir_all(directory)?;
+    let name: String = config
+        .name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect();
+    let stem = format!(
+        "{}-{}",
+        if name.is_empty() { "texture" } else { &name },
+        timestamp(SystemTime::now())
+    );
+
+    let taken = |stem: &str| {
+        directory.join(format!("{}.json", stem)).exists()
+            || files
+                .iter()
+                .any(|(suffix, _)| directory.join(format!("{}{}", stem, suffix)).exists())
+    };
+    let mut unique = stem.clone();
+    let mut number = 1;
+    while taken(&unique) {
+        number += 1;
+        unique = format!("{}-{}", stem, number);
+    }
+
+    let mut paths = vec![];
+    for (suffix, data) in files {
+        let path = directory.join(format!("{}{}", unique, suffix));
+        fs::write(&path, data)?;
+        paths.push(path);
+    }
+    save_config_file(&directory.join(format!("{}.json", unique)), config)?;
+    Ok(paths)
+}
+
+// yyyymmdd-hhmmss in utc
+fn timestamp(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let (days, seconds) = ((seconds / 86400) as i64, seconds % 86400);
+
+    // civil_from_days, http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
     )
-    .is_err()
-    {
-        println!("failed to save image");
+}
+
+pub fn serialize_config(config: &Config) -> String {
+    serde_json::to_string(config).unwrap()
+}
+
+// accepts JSON or a png data url as produced by texture_to_png_base64
+pub fn deserialize_config(data: &str) -> Result<Config, ConfigError> {
+    if let Some(encoded) = data.trim().strip_prefix(PNG_DATA_URL_PREFIX) {
+        return config_from_png(&base64::decode(encoded)?);
+    }
+    let value: serde_json::Value = serde_json::from_str(data)?;
+    let config = match value {
+        // parse from the text again so errors carry line and column
+        serde_json::Value::Object(_) => serde_json::from_str(data)?,
+        serde_json::Value::Array(_) => migrate_legacy_config(value)?,
+        _ => return Err(ConfigError::UnknownFormat),
     };
+    config.validate()
+}
+
+type RulesData = Vec<(String, String)>;
+type ColorsData = Vec<(char, [u8; 4])>;
 
-    format!("{}{}", "data:image/png;base64,", base64::encode(output))
+// version 1, the seed was added as an optional third element
+fn migrate_legacy_config(value: serde_json::Value) -> Result<Config, ConfigError> {
+    let (rules, colors, seed): (RulesData, ColorsData, Option<u64>) =
+        match serde_json::from_value::<(RulesData, ColorsData, u64)>(value.clone()) {
+            Ok((rules, colors, seed)) => (rules, colors, Some(seed)),
+            Err(_) => {
+                let (rules, colors) = serde_json::from_value::<(RulesData, ColorsData)>(value)?;
+                (rules, colors, None)
+            }
+        };
+    Ok(Config {
+        version: CONFIG_VERSION,
+        name: String::new(),
+        author: String::new(),
+        rules: rules
+            .into_iter()
+            .map(|(condition, action)| {
+                let (condition, action) = migrate_legacy_rule(&condition, &action);
+                RuleConfig {
+                    condition,
+                    action,
+                    neighborhood: Neighborhood::default(),
+                }
+            })
+            .collect(),
+        palette: colors
+            .into_iter()
+            .map(|(letter, color)| PaletteEntry { letter, color })
+            .collect(),
+        dimensions: None,
+        seed,
+        steps: 0,
+        apply_mode: ApplyMode::default(),
+        boundary: Boundary::default(),
+    })
 }
 
-pub fn serialize_config(rules: &Vec<Rule>, colors: &BTreeMap<char, [u8; 4]>) -> String {
-    let mut rules_data: Vec<(String, String)> = vec![];
-    for rule in rules {
-        rules_data.push((
-            rule.original_condition().to_string(),
-            rule.original_action().to_string(),
-        ));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neighborhood::{MAX_OFFSETS, MAX_RADIUS};
+
+    fn config_with_dimensions(width: usize, height: usize) -> Config {
+        let mut sprite_gen = SpriteGen::new(4, 4);
+        sprite_gen.randomize_with_seed(1);
+        let mut config = Config::from_sprite_gen(&sprite_gen);
+        config.dimensions = Some(Dimensions { width, height });
+        config
+    }
+
+    #[test]
+    fn oversized_dimensions_are_rejected() {
+        let config = config_with_dimensions(MAX_DIMENSION, MAX_CELLS / MAX_DIMENSION);
+        assert!(deserialize_config(&serialize_config(&config)).is_ok());
+
+        for (width, height) in [
+            (MAX_DIMENSION + 1, 1),
+            (1, usize::MAX),
+            (MAX_DIMENSION, MAX_DIMENSION),
+        ] {
+            let config = config_with_dimensions(width, height);
+            assert!(matches!(
+                deserialize_config(&serialize_config(&config)),
+                Err(ConfigError::TooLarge(_))
+            ));
+            // the same config embedded in a png
+            let png = texture_to_png(&[0; 4], 1, 1, &config).unwrap();
+            assert!(matches!(
+                config_from_png(&png),
+                Err(ConfigError::TooLarge(_))
+            ));
+        }
     }
-    let mut colors_data: Vec<(char, [u8; 4])> = vec![];
-    for (letter, color) in colors.iter() {
-        colors_data.push((*letter, *color));
+
+    #[test]
+    fn oversized_neighborhoods_are_rejected() {
+        let mut config = config_with_dimensions(4, 4);
+        config.rules.push(RuleConfig {
+            condition: "A".into(),
+            action: "5B".into(),
+            neighborhood: Neighborhood::Moore(MAX_RADIUS),
+        });
+        assert!(deserialize_config(&serialize_config(&config)).is_ok());
+
+        for neighborhood in [
+            Neighborhood::Moore(u32::MAX),
+            Neighborhood::VonNeumann(MAX_RADIUS + 1),
+            Neighborhood::Custom(vec![(0, 0), (0, i32::MAX)]),
+            Neighborhood::Custom(vec![(0, 0); MAX_OFFSETS + 1]),
+        ] {
+            config.rules.last_mut().unwrap().neighborhood = neighborhood;
+            assert!(matches!(
+                config.clone().validate(),
+                Err(ConfigError::Neighborhood(_))
+            ));
+            assert!(deserialize_config(&serialize_config(&config)).is_err());
+            let png = texture_to_png(&[0; 4], 1, 1, &config).unwrap();
+            assert!(config_from_png(&png).is_err());
+        }
     }
-    serde_json::to_string(&(rules_data, colors_data)).unwrap()
-}
 
-pub fn deserialize_config(data: &str) -> Option<(Vec<Rule>, BTreeMap<char, [u8; 4]>)> {
-    let (parsed_rules, parsed_colors): (Vec<(String, String)>, Vec<(char, [u8; 4])>) =
-        serde_json::from_str(data).ok()?;
-    let mut rules: Vec<Rule> = vec![];
-    for (condition, action) in parsed_rules {
-        rules.push(Rule::new(&condition, &action));
+    #[test]
+    fn transparent_letters_export_with_their_alpha() {
+        use crate::random_rules::{Harmony, Transparency};
+
+        let mut sprite_gen = SpriteGen::new(3, 1);
+        sprite_gen.char_texture.pixels = vec!['A', 'B', 'C'];
+        sprite_gen.char_color = BTreeMap::from([('A', [0; 4]), ('B', [0; 4])]);
+        // every letter transparent, then none
+        let mut colors = vec![];
+        for chance in [1.0, 0.0] {
+            let transparency = Transparency {
+                chance,
+                alpha_range: (0, 0),
+            };
+            sprite_gen.randomize_color_with(Harmony::default(), transparency);
+            colors.push(sprite_gen.char_color.clone());
+        }
+        assert!(colors[0].values().all(|color| color[3] == 0));
+        assert!(colors[1].values().all(|color| color[3] == 255));
+        // C has no color
+        sprite_gen.char_color = BTreeMap::from([('A', colors[0][&'A']), ('B', colors[1][&'B'])]);
+
+        let mut texture = vec![0; 3 * 4];
+        sprite_gen.update_texture(&mut texture);
+        let png = texture_to_png(&texture, 3, 1, &Config::from_sprite_gen(&sprite_gen)).unwrap();
+        let mut reader = png::Decoder::new(png.as_slice()).read_info().unwrap();
+        assert_eq!(reader.info().color_type, png::ColorType::Rgba);
+        let mut decoded = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut decoded).unwrap();
+        assert_eq!(decoded, texture);
+        let alphas: Vec<u8> = decoded.chunks(4).map(|pixel| pixel[3]).collect();
+        assert_eq!(alphas, [0, 255, 0]);
     }
-    let mut colors = BTreeMap::new();
-    for (color_key, color_value) in parsed_colors {
-        colors.insert(color_key, color_value);
+
+    #[test]
+    fn legacy_configs_round_trip_as_version_2() {
+        let legacy = r#"[[["A", "5B"], ["(?:B.*){2}", "AC[0.5]"]],
+            [["A", [255, 0, 0, 255]], ["B", [0, 0, 255, 128]]], 42]"#;
+        let config = deserialize_config(legacy).unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(config.seed, Some(42));
+        assert_eq!(config.dimensions, None);
+        assert_eq!(
+            config
+                .rules
+                .iter()
+                .map(|rule| (rule.condition.as_str(), rule.action.as_str()))
+                .collect::<Vec<_>>(),
+            vec![("A", "5B"), ("(?:B.*){2}", "AC[0.5]")]
+        );
+        assert!(config
+            .rules
+            .iter()
+            .all(|rule| rule.neighborhood == Neighborhood::default()));
+        assert_eq!(
+            config.colors(),
+            BTreeMap::from([('A', [255, 0, 0, 255]), ('B', [0, 0, 255, 128])])
+        );
+
+        // saved again it is a version 2 object that loads to the same config
+        let saved = serialize_config(&config);
+        assert!(saved.starts_with('{'));
+        assert_eq!(deserialize_config(&saved).unwrap(), config);
+        let pretty = serde_json::to_string_pretty(&config).unwrap();
+        assert_eq!(deserialize_config_bytes(pretty.as_bytes()).unwrap(), config);
+
+        // and generates the same texture
+        let textures: Vec<Vec<char>> = [legacy, saved.as_str()]
+            .iter()
+            .map(|data| {
+                let mut sprite_gen = SpriteGen::new(16, 16);
+                deserialize_config(data).unwrap().load_into(&mut sprite_gen);
+                crate::texture_noise::noise_fill(&mut sprite_gen);
+                for _ in 0..5 {
+                    sprite_gen.apply();
+                }
+                sprite_gen.char_texture.pixels
+            })
+            .collect();
+        assert_eq!(textures[0], textures[1]);
+
+        // the seed is optional in version 1
+        let config = deserialize_config(r#"[[["A", "5B"]], [["A", [1, 2, 3, 4]]]]"#).unwrap();
+        assert_eq!(config.seed, None);
+        assert_eq!(
+            deserialize_config(&serialize_config(&config)).unwrap(),
+            config
+        );
     }
-    Some((rules, colors))
 }
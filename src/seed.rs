@@ -0,0 +1,72 @@
+use rand::{Rng, RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+/*
+every random decision is drawn from a stream derived from a single u64 seed.
+each subsystem gets its own stream so that e.g. re-rolling colors does not
+change the noise or the rule run of the same seed.
+
+ChaCha is used over StdRng because its output is specified and stable across
+rand versions and platforms.
+*/
+pub type SeededRng = ChaCha8Rng;
+
+// RuleRerolls and ColorRerolls are for new rules and colors on top of the ones the seed
+// made from Rules and Colors, so the first re-roll after seeding is not the same draw again
+#[derive(Debug, Clone, Copy)]
+pub enum Stream {
+    Letters,
+    Rules,
+    Colors,
+    Noise,
+    Apply,
+    RuleRerolls,
+    ColorRerolls,
+}
+
+pub fn stream_rng(seed: u64, stream: Stream) -> SeededRng {
+    let mut rng = SeededRng::seed_from_u64(seed);
+    rng.set_stream(stream as u64);
+    rng
+}
+
+// rng of one cell in a pass over the texture. the seed is drawn once per pass from the
+// caller's stream, so a cell gets the same numbers whichever other cells are evaluated
+// and in whatever order. splitmix64, cheap enough to set up for every matching cell and
+// fixed like ChaCha
+pub struct CellRng(u64);
+
+pub fn cell_rng(seed: u64, cell: usize) -> CellRng {
+    CellRng(seed ^ (cell as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15))
+}
+
+impl RngCore for CellRng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+// new master seed from entropy, the only place randomness is not reproducible
+pub fn random_seed() -> u64 {
+    rand::thread_rng().gen()
+}
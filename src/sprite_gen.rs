@@ -1,123 +1,703 @@
 use std::collections::BTreeMap;
+use std::sync::Arc;
 
 use rand::seq::SliceRandom;
-use rand::{prelude::ThreadRng, Rng};
+use rand::Rng;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 
 use crate::char_texture::*;
-use crate::random_rules::{ColorSettings, RuleSettings, SpriteSettings};
+use crate::chunks::map_row_chunks;
+use crate::convergence::{Convergence, ConvergenceTracker, DEFAULT_MAX_PERIOD};
+use crate::history::GenState;
+use crate::incremental::{LastStep, Region, StepKey};
+use crate::letter_grid::{check_grid_letters, check_grid_size, GridError};
+use crate::palette::{apply_palette, LockedPalette, PaletteOrder};
+use crate::random_rules::{ColorSettings, Harmony, RuleSettings, SpriteSettings, Transparency};
+use crate::render::{render, RenderMode};
 use crate::rule::*;
+use crate::seed::{cell_rng, random_seed, stream_rng, SeededRng, Stream};
 use crate::texture_noise::*;
 
 pub struct SpriteGen {
     pub char_texture: CharTexture,
     pub rules: Vec<Rule>,
     pub char_color: BTreeMap<char, [u8; 4]>,
+    pub apply_mode: ApplyMode,
+    pub convergence: ConvergenceTracker,
+    // colors that randomize and randomize_color keep instead of drawing new ones
+    pub locked_palette: Option<LockedPalette>,
+    pub render_mode: RenderMode,
+    seed: u64,
+    steps: usize,
+    rule_rng: SeededRng,
+    color_rng: SeededRng,
+    apply_rng: SeededRng,
+    last_step: Option<LastStep>,
+    drawn_generation: Option<u64>,
+    // the step each cell last changed in, for RenderMode::Age
+    changed_at: Vec<usize>,
+    // 0..1 per cell, what the last noise fill picked the letters from
+    fill_noise: Vec<f32>,
 }
 
 impl SpriteGen {
     pub fn new(width: usize, height: usize) -> Self {
+        let seed = random_seed();
         Self {
             char_texture: CharTexture::new(width, height),
             rules: vec![],
             char_color: BTreeMap::new(),
+            apply_mode: ApplyMode::default(),
+            convergence: ConvergenceTracker::new(DEFAULT_MAX_PERIOD),
+            locked_palette: None,
+            render_mode: RenderMode::default(),
+            seed,
+            steps: 0,
+            rule_rng: stream_rng(seed, Stream::RuleRerolls),
+            color_rng: stream_rng(seed, Stream::ColorRerolls),
+            apply_rng: stream_rng(seed, Stream::Apply),
+            last_step: None,
+            drawn_generation: None,
+            changed_at: vec![],
+            fill_noise: vec![],
         }
     }
 
-    // apply all rules to all pixels
+    // apply all rules to all pixels. in the synchronous mode a step after a step with the
+    // same rules only revisits the cells around the ones that changed, with the same result
     pub fn apply(&mut self) {
-        let mut rng = rand::thread_rng();
-        let input = self.char_texture.full_stringify();
-        apply_rules(&mut rng, &mut self.char_texture, &self.rules, &input);
+        let generation = self.char_texture.generation();
+        let dirty = self.char_texture.take_dirty();
+        // painted, filled or restored since the last step
+        let cells = self.char_texture.pixels.len();
+        match &dirty {
+            Dirty::All => self.changed_at = vec![self.steps; cells],
+            Dirty::Cells(_) if self.changed_at.len() != cells => {
+                self.changed_at = vec![self.steps; cells]
+            }
+            Dirty::Cells(changed) => {
+                for index in changed {
+                    self.changed_at[*index] = self.steps;
+                }
+            }
+        }
+        let key = StepKey::new(&self.rules, self.char_texture.boundary, self.apply_mode);
+        let last_step = self
+            .last_step
+            .take()
+            .filter(|last_step| last_step.key == key);
+        match self.apply_mode {
+            ApplyMode::Synchronous(policy) if policy != ConflictPolicy::Random => {
+                let region = last_step.and_then(|last_step| {
+                    Region::new(&self.char_texture, &self.rules, &dirty, &last_step.active)
+                });
+                let input = match region {
+                    Some(_) => RuleInputs::snapshot(&self.char_texture),
+                    None => RuleInputs::new(&self.char_texture, &self.rules),
+                };
+                let active = apply_rules_synchronous(
+                    &mut self.apply_rng,
+                    &mut self.char_texture,
+                    &self.rules,
+                    &input,
+                    policy,
+                    region.as_ref(),
+                );
+                self.last_step = Some(LastStep {
+                    key: key.clone(),
+                    active,
+                });
+            }
+            mode => {
+                let input = RuleInputs::new(&self.char_texture, &self.rules);
+                apply_rules(
+                    &mut self.apply_rng,
+                    &mut self.char_texture,
+                    &self.rules,
+                    &input,
+                    mode,
+                );
+            }
+        }
+        self.steps += 1;
+        for index in self.char_texture.dirty_cells() {
+            self.changed_at[*index] = self.steps;
+        }
+        let random = matches!(
+            self.apply_mode,
+            ApplyMode::Sequential | ApplyMode::Synchronous(ConflictPolicy::Random)
+        ) || self
+            .rules
+            .iter()
+            .any(|rule| rule.condition().is_some() && rule.is_random());
+        self.convergence
+            .record(&self.char_texture, generation, key, self.steps, random);
+        // every cell is a step older even if no letter changed
+        if let RenderMode::Age { .. } = self.render_mode {
+            self.set_changed();
+        }
+    }
+
+    // step until the texture is stable or oscillating, at most max_steps times
+    pub fn run_until_stable(&mut self, max_steps: usize) -> Option<Convergence> {
+        for _ in 0..max_steps {
+            if self.convergence.result().is_some() {
+                break;
+            }
+            self.apply();
+        }
+        self.convergence.result()
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // restart all random streams from a new master seed
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rule_rng = stream_rng(seed, Stream::RuleRerolls);
+        self.color_rng = stream_rng(seed, Stream::ColorRerolls);
+        self.reset_steps();
+    }
+
+    // number of times the rules were applied since the last noise fill
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    pub fn reset_steps(&mut self) {
+        self.steps = 0;
+        self.apply_rng = stream_rng(self.seed, Stream::Apply);
     }
 
     pub fn randomize(&mut self) {
-        let seed = SpriteSettings::random();
-        self.char_color = seed.colors.into_iter().collect();
-        self.rules = seed.rules;
+        self.randomize_with_seed(random_seed());
+    }
+
+    // letters, rules, colors and image are all derived from the seed
+    pub fn randomize_with_seed(&mut self, seed: u64) {
+        self.set_seed(seed);
+        let settings = SpriteSettings::from_seed(seed);
+        self.char_color = settings.colors.into_iter().collect();
+        if let Some(locked) = &self.locked_palette {
+            // the new letters get the locked colors, ordered by the colors drawn for them
+            apply_palette(&mut self.char_color, &locked.colors, locked.order);
+        }
+        self.rules = settings.rules;
+
+        noise_fill(self);
+        self.set_changed();
+    }
+
+    /*
+    start from the given letters instead of noise, e.g. an imported letter grid. the texture
+    takes the grid's size and keeps its boundary, letters without a color are added as black
+    so they show up in the palette. only A to Z and the fill char are taken, see is_grid_letter,
+    and grids up to the config size limits
+    */
+    pub fn set_letters(&mut self, grid: &CharTexture) -> Result<(), GridError> {
+        let (width, height) = grid.dimensions;
+        check_grid_size(width, height)?;
+        check_grid_letters(&grid.pixels)?;
+        self.char_texture.resize(width, height);
+        self.char_texture.pixels.copy_from_slice(&grid.pixels);
+        self.char_texture.mark_all_dirty();
+        for letter in grid.pixels.iter() {
+            if *letter != CharTexture::FILL_CHAR {
+                self.char_color.entry(*letter).or_insert([0, 0, 0, 255]);
+            }
+        }
+        self.reset_steps();
+        self.set_changed();
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub(crate) fn last_step(&self) -> Option<&LastStep> {
+        self.last_step.as_ref()
+    }
 
+    pub fn randomize_image(&mut self) {
+        self.set_seed(random_seed());
         noise_fill(self);
     }
 
     pub fn randomize_color(&mut self) {
-        let mut rng = rand::thread_rng();
+        self.randomize_color_with(Harmony::default(), Transparency::default());
+    }
+
+    // new colors picked by the harmony, some of them see-through. a locked palette is kept
+    pub fn randomize_color_with(&mut self, harmony: Harmony, transparency: Transparency) {
+        if self.locked_palette.is_some() {
+            return;
+        }
         let letters: Vec<char> = self.char_color.keys().map(|c| c.to_owned()).collect();
-        let color_settings = ColorSettings::random(&mut rng);
+        let mut color_settings = ColorSettings::random(&mut self.color_rng);
+        color_settings.harmony = harmony;
+        color_settings.transparency = transparency;
         self.char_color = color_settings
-            .generate(&mut rng, &letters)
+            .generate(&mut self.color_rng, &letters, &self.rules)
             .into_iter()
             .collect();
+        self.set_changed();
+    }
+
+    // colors from a palette file on the letters. a locked palette is replaced by them
+    pub fn set_palette(&mut self, colors: &[[u8; 4]], order: PaletteOrder) {
+        apply_palette(&mut self.char_color, colors, order);
+        if self.locked_palette.is_some() {
+            self.locked_palette = Some(LockedPalette {
+                colors: colors.to_vec(),
+                order,
+            });
+        }
+        self.set_changed();
+    }
+
+    // keep the current colors, in letter order
+    pub fn lock_palette(&mut self) {
+        self.locked_palette = Some(LockedPalette {
+            colors: self.char_color.values().copied().collect(),
+            order: PaletteOrder::InOrder,
+        });
     }
 
     pub fn randomize_rules(&mut self) {
-        let mut rng = rand::thread_rng();
         let letters: Vec<char> = self.char_color.keys().map(|c| c.to_owned()).collect();
-        let rule_settings = RuleSettings::random(&mut rng);
-        self.rules = rule_settings.generate(&mut rng, &letters);
+        let rule_settings = RuleSettings::random(&mut self.rule_rng);
+        self.rules = rule_settings.generate(&mut self.rule_rng, &letters);
     }
 
+    pub(crate) fn state(&self) -> GenState {
+        GenState {
+            rules: self.rules.clone(),
+            char_color: self.char_color.clone(),
+            apply_mode: self.apply_mode,
+            boundary: self.char_texture.boundary,
+            seed: self.seed,
+            steps: self.steps,
+            rngs: [
+                self.rule_rng.clone(),
+                self.color_rng.clone(),
+                self.apply_rng.clone(),
+            ],
+        }
+    }
+
+    // the letters are restored by History, they are not part of the state
+    pub(crate) fn restore_state(&mut self, state: &GenState) {
+        self.rules = state.rules.clone();
+        self.char_color = state.char_color.clone();
+        self.apply_mode = state.apply_mode;
+        self.char_texture.boundary = state.boundary;
+        self.seed = state.seed;
+        self.steps = state.steps;
+        let [rule_rng, color_rng, apply_rng] = state.rngs.clone();
+        self.rule_rng = rule_rng;
+        self.color_rng = color_rng;
+        self.apply_rng = apply_rng;
+        self.last_step = None;
+        self.convergence.reset();
+        self.set_changed();
+    }
+
+    // rgba, alpha included
     pub fn update_texture(&self, texture: &mut [u8]) {
-        for (index, char) in self.char_texture.get_array().iter().enumerate() {
-            let offset_index = index * 4;
-            for channel in 0..3 {
-                if let Some(color_channels) = self.char_color.get(char) {
-                    texture[offset_index + channel] = color_channels[channel];
-                } else {
-                    // if no color, make transparent
-                    texture[offset_index + channel] = 0;
-                }
-            }
+        render(self, texture);
+    }
+
+    // steps since the cell's letter last changed, 0 before the first step after a fill
+    pub fn cell_age(&self, index: usize) -> usize {
+        let changed_at = self.changed_at.get(index).copied().unwrap_or(self.steps);
+        self.steps.saturating_sub(changed_at)
+    }
+
+    // empty when the texture was not noise filled at its current size
+    pub fn fill_noise(&self) -> &[f32] {
+        if self.fill_noise.len() == self.char_texture.pixels.len() {
+            &self.fill_noise
+        } else {
+            &[]
         }
     }
 
-    pub fn set_changed(&mut self) {
-        self.char_texture.changed = true;
+    pub(crate) fn set_fill_noise(&mut self, noise: Vec<f32>) {
+        self.fill_noise = noise;
     }
 
+    // the displayed texture is stale, a letter changed or set_changed was called since
+    // the last clear_changed
     pub fn is_changed(&self) -> bool {
-        self.char_texture.changed
+        self.drawn_generation != Some(self.char_texture.generation())
+    }
+
+    pub fn set_changed(&mut self) {
+        self.drawn_generation = None;
     }
+
+    pub fn clear_changed(&mut self) {
+        self.drawn_generation = Some(self.char_texture.generation());
+    }
+}
+
+/*
+how the writes of one step are applied
+
+Sequential: rules run in a random order and write straight into the texture,
+    conditions are matched against the texture as it was at the start of the step
+Synchronous: every rule reads the previous generation and writes into a separate
+    next generation, rules run in list order and ConflictPolicy picks the value
+    when more than one action targets the same cell
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ApplyMode {
+    #[default]
+    Sequential,
+    Synchronous(ConflictPolicy),
 }
 
-pub fn apply_rules(rng: &mut ThreadRng, texture: &mut CharTexture, rules: &[Rule], input: &str) {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConflictPolicy {
+    // the last write in rule order wins
+    LastWins,
+    // the first write in rule order wins, earlier rules have priority
+    Priority,
+    // every write targeting the cell is equally likely to win
+    Random,
+}
+
+// the letters at the start of a step, which every rule reads from. rules without a
+// compiled condition also get the texture stringified once per distinct neighborhood
+pub struct RuleInputs {
+    texture: CharTexture,
+    inputs: Vec<RuleInput>,
+}
+
+// the offsets of a neighborhood and the texture stringified with them
+type RuleInput = (Arc<[(i32, i32)]>, String);
+
+impl RuleInputs {
+    pub fn new(texture: &CharTexture, rules: &[Rule]) -> Self {
+        let mut inputs: Vec<RuleInput> = vec![];
+        for rule in rules {
+            if rule.condition().is_none() || rule.compiled().is_some() {
+                continue;
+            }
+            if !inputs
+                .iter()
+                .any(|(offsets, _)| **offsets == *rule.offsets())
+            {
+                let input = texture.full_stringify_offsets(rule.offsets());
+                inputs.push((rule.shared_offsets(), input));
+            }
+        }
+        Self {
+            texture: texture.clone(),
+            inputs,
+        }
+    }
+
+    // no stringified inputs, for steps that only look at a few cells
+    pub fn snapshot(texture: &CharTexture) -> Self {
+        Self {
+            texture: texture.clone(),
+            inputs: vec![],
+        }
+    }
+
+    pub fn texture(&self) -> &CharTexture {
+        &self.texture
+    }
+
+    // all cells for the rule's neighborhood, rule.offsets().len() chars per cell
+    pub fn for_rule(&self, rule: &Rule) -> Option<&str> {
+        self.inputs
+            .iter()
+            .find(|(offsets, _)| **offsets == *rule.offsets())
+            .map(|(_, input)| input.as_str())
+    }
+}
+
+pub fn apply_rules(
+    rng: &mut SeededRng,
+    texture: &mut CharTexture,
+    rules: &[Rule],
+    input: &RuleInputs,
+    mode: ApplyMode,
+) {
+    match mode {
+        ApplyMode::Sequential => apply_rules_sequential(rng, texture, rules, input),
+        ApplyMode::Synchronous(policy) => {
+            apply_rules_synchronous(rng, texture, rules, input, policy, None);
+        }
+    }
+}
+
+fn apply_rules_sequential(
+    rng: &mut SeededRng,
+    texture: &mut CharTexture,
+    rules: &[Rule],
+    input: &RuleInputs,
+) {
     let mut rule_indices: Vec<usize> = (0..rules.len()).collect();
     rule_indices.shuffle(rng);
 
     for rule_index in rule_indices {
         let rule = &rules[rule_index];
-        if !rule.original_condition().is_empty() {
-            for index in 0..texture.pixels.len() {
-                let start_index = index * 9;
-                let end_index = start_index + 9;
-                let match_slice = &input[start_index..end_index];
-
-                if rule.condition().is_match(match_slice) {
-                    let (x, y) = texture.xy_from_index(index);
-                    apply_actions(texture, rules[rule_index].action(), rng, match_slice, x, y);
+        if let Some(condition) = rule.condition() {
+            for chunk in rule_writes(rng, rule, condition, input, None) {
+                for (target, value) in chunk.writes {
+                    texture.set_index(target, value);
+                }
+            }
+        }
+    }
+}
+
+// with a region only its cells are evaluated and its targets written, see incremental.rs.
+// returns the cells that matched a rule with random actions
+fn apply_rules_synchronous(
+    rng: &mut SeededRng,
+    texture: &mut CharTexture,
+    rules: &[Rule],
+    input: &RuleInputs,
+    policy: ConflictPolicy,
+    region: Option<&Region>,
+) -> Vec<usize> {
+    let mut next: Vec<Option<char>> = vec![None; texture.pixels.len()];
+    // writes seen per cell, for picking uniformly among them one at a time
+    let mut write_counts: Vec<u32> = vec![];
+    if policy == ConflictPolicy::Random {
+        write_counts = vec![0; texture.pixels.len()];
+    }
+
+    let mut active = vec![];
+    for rule in rules {
+        if let Some(condition) = rule.condition() {
+            for chunk in rule_writes(rng, rule, condition, input, region) {
+                active.extend(chunk.random_matches);
+                for (target, value) in chunk.writes {
+                    if let Some(region) = region {
+                        if !region.targets[target] {
+                            continue;
+                        }
+                    }
+                    resolve_write(rng, policy, &mut next, &mut write_counts, target, value);
                 }
             }
         }
     }
+
+    for (index, value) in next.into_iter().enumerate() {
+        if let Some(value) = value {
+            texture.set_index(index, value);
+        }
+    }
+    active
+}
+
+// one write into the next generation, ConflictPolicy decides whether it replaces an earlier one
+fn resolve_write(
+    rng: &mut SeededRng,
+    policy: ConflictPolicy,
+    next: &mut [Option<char>],
+    write_counts: &mut [u32],
+    target: usize,
+    value: char,
+) {
+    match policy {
+        ConflictPolicy::LastWins => next[target] = Some(value),
+        ConflictPolicy::Priority => {
+            if next[target].is_none() {
+                next[target] = Some(value);
+            }
+        }
+        ConflictPolicy::Random => {
+            write_counts[target] += 1;
+            if rng.gen_range(0..write_counts[target]) == 0 {
+                next[target] = Some(value);
+            }
+        }
+    }
+}
+
+/*
+the step as it was before rules were matched in chunks: one thread, every cell through the
+regex on the stringified texture and one rng for all cells. only kept as the baseline of
+benches/apply.rs, the same seed gives other textures than apply_rules
+*/
+pub fn apply_rules_serial(
+    rng: &mut SeededRng,
+    texture: &mut CharTexture,
+    rules: &[Rule],
+    mode: ApplyMode,
+) {
+    let previous = texture.clone();
+    let mut inputs: Vec<(&[(i32, i32)], String)> = vec![];
+    for rule in rules {
+        if !inputs.iter().any(|(offsets, _)| *offsets == rule.offsets()) {
+            let input = previous.full_stringify_offsets(rule.offsets());
+            inputs.push((rule.offsets(), input));
+        }
+    }
+    let mut rule_indices: Vec<usize> = (0..rules.len()).collect();
+    if mode == ApplyMode::Sequential {
+        rule_indices.shuffle(rng);
+    }
+    let mut next: Vec<Option<char>> = vec![None; texture.pixels.len()];
+    let mut write_counts: Vec<u32> = vec![0; texture.pixels.len()];
+
+    let mut writes = vec![];
+    for rule_index in rule_indices {
+        let rule = &rules[rule_index];
+        let condition = match rule.condition() {
+            Some(condition) => condition,
+            None => continue,
+        };
+        let size = rule.offsets().len();
+        let (_, input) = inputs
+            .iter()
+            .find(|(offsets, _)| *offsets == rule.offsets())
+            .unwrap();
+        for index in 0..texture.pixels.len() {
+            let match_slice = &input[index * size..(index + 1) * size];
+            if condition.is_match(match_slice) {
+                let (x, y) = previous.xy_from_index(index);
+                apply_actions(&previous, rule, rng, match_slice, x, y, &mut writes);
+                for (target, value) in writes.drain(..) {
+                    match mode {
+                        ApplyMode::Sequential => texture.set_index(target, value),
+                        ApplyMode::Synchronous(policy) => {
+                            resolve_write(rng, policy, &mut next, &mut write_counts, target, value)
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    for (index, value) in next.into_iter().enumerate() {
+        if let Some(value) = value {
+            texture.set_index(index, value);
+        }
+    }
+}
+
+// what one chunk of rows gave for a rule
+struct ChunkWrites {
+    writes: Vec<(usize, char)>,
+    // matching cells, only kept for rules with random actions
+    random_matches: Vec<usize>,
+}
+
+// writes of one rule in pixel order. the texture is matched in chunks of rows on all
+// threads and every matching cell draws from its own rng, so the writes depend neither
+// on the number of threads nor on which cells are evaluated. the caller applies them.
+fn rule_writes(
+    rng: &mut SeededRng,
+    rule: &Rule,
+    condition: &Regex,
+    input: &RuleInputs,
+    region: Option<&Region>,
+) -> Vec<ChunkWrites> {
+    let texture = input.texture();
+    let rule_input = input.for_rule(rule);
+    let offsets = rule.offsets();
+    let size = offsets.len();
+    let random = rule.is_random();
+    let seed: u64 = rng.gen();
+    map_row_chunks(
+        texture.dimensions.0,
+        texture.pixels.len(),
+        // a clone per thread gets its own match cache instead of sharing one
+        || condition.clone(),
+        |condition, range| {
+            let mut chunk = ChunkWrites {
+                writes: vec![],
+                random_matches: vec![],
+            };
+            let mut neighbors = String::with_capacity(size);
+            for index in range {
+                if let Some(region) = region {
+                    if !region.evaluate[index] {
+                        continue;
+                    }
+                }
+                let (x, y) = texture.xy_from_index(index);
+                let match_slice = match (rule.compiled(), rule_input) {
+                    // the neighborhood is only stringified for the actions of matching cells
+                    (Some(compiled), _) => {
+                        if !compiled.matches(texture, x, y, offsets) {
+                            continue;
+                        }
+                        neighbors.clear();
+                        neighbors
+                            .extend(offsets.iter().map(|offset| texture.neighbor(x, y, *offset)));
+                        neighbors.as_str()
+                    }
+                    (None, Some(rule_input)) => {
+                        let match_slice = &rule_input[index * size..(index + 1) * size];
+                        if !condition.is_match(match_slice) {
+                            continue;
+                        }
+                        match_slice
+                    }
+                    (None, None) => {
+                        neighbors.clear();
+                        neighbors
+                            .extend(offsets.iter().map(|offset| texture.neighbor(x, y, *offset)));
+                        if !condition.is_match(&neighbors) {
+                            continue;
+                        }
+                        neighbors.as_str()
+                    }
+                };
+                if random {
+                    chunk.random_matches.push(index);
+                }
+                let mut rng = cell_rng(seed, index);
+                apply_actions(
+                    texture,
+                    rule,
+                    &mut rng,
+                    match_slice,
+                    x,
+                    y,
+                    &mut chunk.writes,
+                );
+            }
+            chunk
+        },
+    )
 }
 
 /* <location><value>[<chance>]
-if <location> is 1-9, use as the relative index
+if <location> is 1-9 or [n], use as the relative index in the rule's neighborhood
 if <location> is A-Z, lookup the indices cells in range matching the letter
-if <location> is *, use all 1-9 as indices
-if <value> is 1-9, use the letter from that cell as the value
+if <location> is *, use all cells of the neighborhood as indices
+if <value> is 1-9 or [n], use the letter from that cell as the value
 if <value> is A-Z, use that letter as the value
 if <value> is *, use a random letter as the value
 <chance> is a nonnegative decimal such that 1.0 >= chance >= std::f32::MIN_POS_VALUE
-    ([A-Z1-9*])([A-Z1-9*])(?:\[([0]?[.][0-9]+)\])?
+
+the resulting (pixel index, letter) pairs are pushed to writes, the caller applies them
 */
 fn apply_actions(
-    texture: &mut CharTexture,
-    actions: &[Action],
-    rng: &mut ThreadRng,
+    texture: &CharTexture,
+    rule: &Rule,
+    rng: &mut impl Rng,
     input: &str,
     x: usize,
     y: usize,
+    writes: &mut Vec<(usize, char)>,
 ) {
-    for action in actions {
+    let size = rule.offsets().len();
+    if size == 0 {
+        return;
+    }
+    for action in rule.action() {
         if let Some(chance) = action.chance {
             if chance < rng.gen_range(0.0..1.0) {
                 continue; // rng failed, skipping
@@ -125,34 +705,254 @@ fn apply_actions(
         }
         let value = match action.value {
             ActionParam::Char(c) => c,
-            ActionParam::Index(i) => input.chars().nth(i - 1).unwrap(),
-            ActionParam::Wildcard => input.chars().nth(rng.gen_range(0..9) as usize).unwrap(),
+            ActionParam::Index(i) => match input.chars().nth(i - 1) {
+                Some(c) => c,
+                None => continue, // index outside the neighborhood
+            },
+            // a random letter of the neighborhood
+            ActionParam::Wildcard => input
+                .chars()
+                .nth(rng.gen_range(0..size as u32) as usize)
+                .unwrap(),
         };
         if value == CharTexture::FILL_CHAR {
             // never propagate FILL_CHAR
             return;
         }
 
-        let mut indices = vec![false; 9];
+        let mut indices = vec![false; size];
         match action.location {
             ActionParam::Char(c) => {
                 indices = input.chars().map(|char| char == c).collect();
             }
             ActionParam::Index(i) => {
+                if i > size {
+                    continue; // index outside the neighborhood
+                }
                 indices[i - 1] = true;
             }
             ActionParam::Wildcard => {
-                indices = vec![true; 9];
+                indices = vec![true; size];
             }
         }
 
-        let valid_indices = texture.get_valid_3x3_indices(x, y);
+        let valid_indices = texture.get_valid_indices(x, y, rule.offsets());
         //println!("value: {}, indices: {:?}, valid indices: {:?}",value, indices, valid_indices);
-        for relative in 0..9 {
+        for relative in 0..size {
             if indices[relative] {
                 if let Some((abs_x, abs_y)) = valid_indices[relative] {
-                    texture.set(abs_x, abs_y, value);
+                    writes.push((texture.index_from_xy(abs_x, abs_y), value));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+    use crate::neighborhood::Neighborhood;
+
+    fn rule_strings(sprite_gen: &SpriteGen) -> Vec<(String, String)> {
+        sprite_gen
+            .rules
+            .iter()
+            .map(|rule| {
+                (
+                    rule.original_condition().to_string(),
+                    rule.original_action().to_string(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reroll_after_seeding_changes_rules_and_colors() {
+        for seed in 0..8 {
+            let mut sprite_gen = SpriteGen::new(16, 16);
+            sprite_gen.randomize_with_seed(seed);
+            let (rules, colors) = (rule_strings(&sprite_gen), sprite_gen.char_color.clone());
+
+            sprite_gen.randomize_rules();
+            assert_ne!(rules, rule_strings(&sprite_gen), "seed {}", seed);
+            sprite_gen.randomize_color();
+            assert_ne!(colors, sprite_gen.char_color, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn reroll_after_set_seed_changes_rules_and_colors() {
+        let mut sprite_gen = SpriteGen::new(16, 16);
+        sprite_gen.randomize_with_seed(3);
+        sprite_gen.set_seed(3);
+        let (rules, colors) = (rule_strings(&sprite_gen), sprite_gen.char_color.clone());
+        sprite_gen.randomize_rules();
+        sprite_gen.randomize_color();
+        assert_ne!(rules, rule_strings(&sprite_gen));
+        assert_ne!(colors, sprite_gen.char_color);
+    }
+
+    #[test]
+    fn thread_count_does_not_change_the_result() {
+        let modes = [
+            ApplyMode::Sequential,
+            ApplyMode::Synchronous(ConflictPolicy::LastWins),
+            ApplyMode::Synchronous(ConflictPolicy::Random),
+        ];
+        let run = |threads: usize, seed: u64, mode: ApplyMode| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            pool.install(|| {
+                let mut sprite_gen = SpriteGen::new(96, 80);
+                sprite_gen.randomize_with_seed(seed);
+                sprite_gen.apply_mode = mode;
+                for _ in 0..8 {
+                    sprite_gen.apply();
                 }
+                sprite_gen.char_texture.pixels
+            })
+        };
+        for seed in 0..4 {
+            for mode in modes {
+                assert_eq!(run(1, seed, mode), run(4, seed, mode), "seed {}", seed);
+            }
+        }
+    }
+
+    // one synchronous step over a row of letters, every rule only looks at its own cell
+    fn synchronous_step(
+        letters: &str,
+        rules: &[(&str, &str)],
+        policy: ConflictPolicy,
+        seed: u64,
+    ) -> String {
+        let mut texture = CharTexture::new(letters.len(), 1);
+        texture.pixels = letters.chars().collect();
+        let rules: Vec<Rule> = rules
+            .iter()
+            .map(|(condition, action)| {
+                Rule::try_new(condition, action)
+                    .unwrap()
+                    .with_neighborhood(Neighborhood::Custom(vec![(0, 0)]))
+            })
+            .collect();
+        let input = RuleInputs::new(&texture, &rules);
+        let mut rng = stream_rng(seed, Stream::Apply);
+        apply_rules(
+            &mut rng,
+            &mut texture,
+            &rules,
+            &input,
+            ApplyMode::Synchronous(policy),
+        );
+        texture.pixels.iter().collect()
+    }
+
+    #[test]
+    fn conflicting_writes_follow_the_policy() {
+        let rules = [("A", "1B"), ("A", "1C")];
+        let swapped = [("A", "1C"), ("A", "1B")];
+        assert_eq!(
+            synchronous_step("AAX", &rules, ConflictPolicy::LastWins, 0),
+            "CCX"
+        );
+        assert_eq!(
+            synchronous_step("AAX", &swapped, ConflictPolicy::LastWins, 0),
+            "BBX"
+        );
+        assert_eq!(
+            synchronous_step("AAX", &rules, ConflictPolicy::Priority, 0),
+            "BBX"
+        );
+        assert_eq!(
+            synchronous_step("AAX", &swapped, ConflictPolicy::Priority, 0),
+            "CCX"
+        );
+
+        // either write can win, the cell without a match keeps its letter
+        let results: BTreeSet<String> = (0..64)
+            .map(|seed| synchronous_step("AX", &rules, ConflictPolicy::Random, seed))
+            .collect();
+        assert_eq!(
+            results,
+            BTreeSet::from(["BX".to_string(), "CX".to_string()])
+        );
+    }
+
+    #[test]
+    fn synchronous_rules_read_the_previous_generation() {
+        // the first rule writes B where the second matches B, in both orders
+        for rules in [[("A", "1B"), ("B", "1D")], [("B", "1D"), ("A", "1B")]] {
+            for policy in [
+                ConflictPolicy::LastWins,
+                ConflictPolicy::Priority,
+                ConflictPolicy::Random,
+            ] {
+                for seed in 0..8 {
+                    assert_eq!(
+                        synchronous_step("AB", &rules, policy, seed),
+                        "BD",
+                        "{:?} {:?}",
+                        rules,
+                        policy
+                    );
+                }
+            }
+        }
+    }
+
+    // one step of a 3x3 rule on a B texture with an A in the middle, only the middle matches
+    fn wildcard_step(action: &str, seed: u64) -> String {
+        let mut texture = CharTexture::new(3, 3);
+        texture.pixels = "BBBBABBBB".chars().collect();
+        let rules = [Rule::try_new("....A....", action).unwrap()];
+        let input = RuleInputs::new(&texture, &rules);
+        let mut rng = stream_rng(seed, Stream::Apply);
+        apply_rules(
+            &mut rng,
+            &mut texture,
+            &rules,
+            &input,
+            ApplyMode::Sequential,
+        );
+        texture.pixels.iter().collect()
+    }
+
+    #[test]
+    fn wildcards_pick_every_cell_or_a_random_letter() {
+        // a * location is every cell of the neighborhood
+        assert_eq!(wildcard_step("*C", 0), "CCCCCCCCC");
+        let mut values = BTreeSet::new();
+        for seed in 0..32 {
+            // a * value is one letter of the neighborhood, written to every location
+            let all = wildcard_step("**", seed);
+            assert!(all == "AAAAAAAAA" || all == "BBBBBBBBB", "{}", all);
+            values.insert(all);
+            // a letter location with a * value only writes the cells with that letter
+            let letters = wildcard_step("B*", seed);
+            assert_eq!(&letters[4..5], "A");
+            assert!(
+                letters == "BBBBABBBB" || letters == "AAAAAAAAA",
+                "{}",
+                letters
+            );
+        }
+        assert_eq!(values.len(), 2);
+    }
+
+    #[test]
+    fn generated_rules_have_no_wildcard_location_with_a_fixed_value() {
+        for seed in 0..64 {
+            for rule in SpriteSettings::from_seed(seed).rules {
+                assert!(rule.is_valid(), "{}", rule.original_action());
+                assert!(rule.action().iter().all(|action| {
+                    action.location != ActionParam::Wildcard
+                        || action.value == ActionParam::Wildcard
+                }));
             }
         }
     }
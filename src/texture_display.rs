@@ -1,9 +1,16 @@
-use crate::sprite_gen::*;
 use crate::ui::*;
+use auto::sprite_gen::*;
 
 use bevy::prelude::*;
 use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
 
+// the sprite showing the texture, as opposed to the tile preview copies around it
+#[derive(Component)]
+pub struct MainSprite;
+
+#[derive(Component)]
+pub struct TilePreview;
+
 pub struct MainTexture {
     pub sprite_gen: SpriteGen,
     pub texture_handle: Handle<Image>,
@@ -24,22 +31,25 @@ pub fn refresh_texture(mut textures: ResMut<Assets<Image>>, mut main_texture: Re
             .get_mut(main_texture.texture_handle.clone())
             .unwrap();
         main_texture.sprite_gen.update_texture(&mut texture.data);
-        main_texture.sprite_gen.set_changed();
+        main_texture.sprite_gen.clear_changed();
     }
 }
 
 pub fn resize_texture(
     mut commands: Commands,
-    sprites: Query<Entity, With<Sprite>>,
+    sprites: Query<Entity, With<MainSprite>>,
     mut textures: ResMut<Assets<Image>>,
     mut main_texture: ResMut<MainTexture>,
     mut ui_context: ResMut<UiContext>,
 ) {
     let new_width = ui_context.texture_dimensions.0;
     let new_height = ui_context.texture_dimensions.1;
-    let old_width = main_texture.sprite_gen.char_texture.dimensions.0;
-    let old_height = main_texture.sprite_gen.char_texture.dimensions.1;
-    let size_changed = new_width != old_width || new_height != old_height;
+    // compare against the displayed image, the char texture may already be resized (config import)
+    let old_size = textures
+        .get(&main_texture.texture_handle)
+        .map(|image| image.size())
+        .unwrap_or_default();
+    let size_changed = new_width != old_size.x as usize || new_height != old_size.y as usize;
 
     if ui_context.update_texture_dimensions {
         ui_context.update_texture_dimensions = false;
@@ -81,15 +91,65 @@ pub fn create_texture(
     ));
 
     let custom_size = Vec2::new((width * 3) as f32, (height * 3) as f32);
-    commands.spawn_bundle(SpriteBundle {
-        texture: texture_handle.clone(),
-        transform: Transform::from_translation(Vec3::new(0., 0., 0.)),
-        sprite: Sprite {
-            custom_size: Some(custom_size),
+    commands
+        .spawn_bundle(SpriteBundle {
+            texture: texture_handle.clone(),
+            transform: Transform::from_translation(Vec3::new(0., 0., 0.)),
+            sprite: Sprite {
+                custom_size: Some(custom_size),
+                ..Default::default()
+            },
             ..Default::default()
-        },
-        ..Default::default()
-    });
+        })
+        .insert(MainSprite);
 
     texture_handle
 }
+
+// surround the texture with 8 copies of itself to check that it tiles
+pub fn tile_preview(
+    mut commands: Commands,
+    ui_context: Res<UiContext>,
+    main_texture: Res<MainTexture>,
+    main_sprite: Query<&Sprite, With<MainSprite>>,
+    previews: Query<(Entity, &Handle<Image>, &Sprite), With<TilePreview>>,
+) {
+    let custom_size = match main_sprite.get_single() {
+        Ok(sprite) => sprite.custom_size,
+        Err(_) => return,
+    };
+    let up_to_date = previews.iter().all(|(_, handle, sprite)| {
+        *handle == main_texture.texture_handle && sprite.custom_size == custom_size
+    });
+    if up_to_date && ui_context.tile_preview != previews.is_empty() {
+        return;
+    }
+
+    for (preview, _, _) in previews.iter() {
+        commands.entity(preview).despawn();
+    }
+
+    let custom_size = match custom_size {
+        Some(custom_size) if ui_context.tile_preview => custom_size,
+        _ => return,
+    };
+    for x in -1..=1 {
+        for y in -1..=1 {
+            if x == 0 && y == 0 {
+                continue;
+            }
+            let translation = Vec3::new(x as f32 * custom_size.x, y as f32 * custom_size.y, 0.);
+            commands
+                .spawn_bundle(SpriteBundle {
+                    texture: main_texture.texture_handle.clone(),
+                    transform: Transform::from_translation(translation),
+                    sprite: Sprite {
+                        custom_size: Some(custom_size),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .insert(TilePreview);
+        }
+    }
+}
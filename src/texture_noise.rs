@@ -1,12 +1,11 @@
 use std::{collections::BTreeMap, vec};
 
+use crate::char_texture::Boundary;
+use crate::seed::{stream_rng, SeededRng, Stream};
 use crate::sprite_gen::SpriteGen;
 use noise::{utils::*, *};
 
-use rand::{
-    prelude::{SliceRandom, ThreadRng},
-    Rng,
-};
+use rand::{prelude::SliceRandom, Rng};
 /*
 NOTE: intermediary steps computed outside of the noise crate to because of
 the API structure making chaining difficult
@@ -25,7 +24,7 @@ struct NoiseSettings {
 }
 
 impl NoiseSettings {
-    pub fn random(rng: &mut ThreadRng) -> Self {
+    pub fn random(rng: &mut SeededRng) -> Self {
         Self {
             generator_chance: rng.gen_range(0.1..0.5),
             modify_chance: rng.gen_range(0.2..0.9),
@@ -64,6 +63,8 @@ struct GeneratedNoiseSettings {
     size: (usize, usize),
     x_bounds: (f64, f64),
     y_bounds: (f64, f64),
+    // blend each layer with its neighbouring tile so the final map wraps around
+    seamless: bool,
 }
 
 struct GeneratedNoise {
@@ -78,6 +79,7 @@ impl GeneratedNoise {
                 .set_size(settings.size.0, settings.size.1)
                 .set_x_bounds(settings.x_bounds.0, settings.x_bounds.1)
                 .set_y_bounds(settings.y_bounds.0, settings.y_bounds.1)
+                .set_is_seamless(settings.seamless)
                 .build(),
             settings,
         }
@@ -112,20 +114,40 @@ impl GeneratedNoise {
 
 impl NoiseFn<[f64; 3]> for GeneratedNoise {
     fn get(&self, point: [f64; 3]) -> f64 {
-        // map f64 within self.bounds to usize within self.size
-        let x_len = self.settings.x_bounds.1 - self.settings.x_bounds.0;
-        let x_step = x_len / self.settings.size.0 as f64;
-        let x = (point[0] / x_step) as usize;
-
-        let y_len = self.settings.y_bounds.1 - self.settings.y_bounds.0;
-        let y_step = y_len / self.settings.size.1 as f64;
-        let y = (point[1] / y_step) as usize;
+        let x = Self::index(
+            point[0],
+            self.settings.x_bounds,
+            self.settings.size.0,
+            self.settings.seamless,
+        );
+        let y = Self::index(
+            point[1],
+            self.settings.y_bounds,
+            self.settings.size.1,
+            self.settings.seamless,
+        );
 
         self.noise.get_value(x, y)
     }
 }
 
-fn random_generator(rng: &mut ThreadRng, settings: GeneratedNoiseSettings) -> GeneratedNoise {
+impl GeneratedNoise {
+    /*
+    map f64 within bounds to usize within size. a seamless builder also samples one extent
+    past the bounds, those wrap back onto the map so derived layers tile too. without
+    wrapping the original mapping stays, seeds give the same textures as before
+    */
+    fn index(value: f64, bounds: (f64, f64), size: usize, seamless: bool) -> usize {
+        let step = (bounds.1 - bounds.0) / size as f64;
+        if !seamless {
+            return (value / step) as usize;
+        }
+        let index = ((value - bounds.0) / step).round() as i64;
+        index.rem_euclid(size as i64) as usize
+    }
+}
+
+fn random_generator(rng: &mut SeededRng, settings: GeneratedNoiseSettings) -> GeneratedNoise {
     match rng.gen_range(0..=8) {
         0 => GeneratedNoise::from_noise(&Perlin::new().set_seed(rng.gen()), settings),
         1 => GeneratedNoise::from_noise(&Worley::new().set_seed(rng.gen()), settings),
@@ -144,7 +166,7 @@ fn random_generator(rng: &mut ThreadRng, settings: GeneratedNoiseSettings) -> Ge
 }
 
 fn random_modifier(
-    rng: &mut ThreadRng,
+    rng: &mut SeededRng,
     source: &dyn NoiseFn<[f64; 3]>,
     settings: GeneratedNoiseSettings,
 ) -> GeneratedNoise {
@@ -170,7 +192,7 @@ fn random_modifier(
 }
 
 fn random_combiner(
-    rng: &mut ThreadRng,
+    rng: &mut SeededRng,
     source1: &dyn NoiseFn<[f64; 3]>,
     source2: &dyn NoiseFn<[f64; 3]>,
     settings: GeneratedNoiseSettings,
@@ -185,21 +207,21 @@ fn random_combiner(
     }
 }
 
-fn random_noise(sprite: &mut SpriteGen) -> NoiseMap {
-    let mut rng = rand::thread_rng();
-    let settings = NoiseSettings::random(&mut rng);
+fn random_noise(rng: &mut SeededRng, sprite: &SpriteGen) -> NoiseMap {
+    let settings = NoiseSettings::random(rng);
 
     let mut last_layer: Vec<GeneratedNoise> = vec![];
     let layer_settings = GeneratedNoiseSettings {
         size: sprite.char_texture.dimensions,
         x_bounds: (-3., 3.),
         y_bounds: (-3., 3.),
+        seamless: sprite.char_texture.boundary == Boundary::Wrap,
     };
 
     // generators
-    last_layer.push(random_generator(&mut rng, layer_settings)); // need at least 1
+    last_layer.push(random_generator(rng, layer_settings)); // need at least 1
     while rng.gen_range(0.0..1.0) < settings.generator_chance {
-        last_layer.push(random_generator(&mut rng, layer_settings));
+        last_layer.push(random_generator(rng, layer_settings));
     }
 
     // main layer loop
@@ -216,7 +238,7 @@ fn random_noise(sprite: &mut SpriteGen) -> NoiseMap {
             used_indices[source1_index] = true;
             used_indices[source2_index] = true;
             next_layer.push(random_combiner(
-                &mut rng,
+                rng,
                 &last_layer[source1_index],
                 &last_layer[source2_index],
                 layer_settings,
@@ -232,7 +254,7 @@ fn random_noise(sprite: &mut SpriteGen) -> NoiseMap {
             if rng.gen_range(0.0..1.0) < settings.modify_chance {
                 used_indices[unused_index] = true;
                 next_layer.push(random_modifier(
-                    &mut rng,
+                    rng,
                     &last_layer[unused_index],
                     layer_settings,
                 ));
@@ -257,23 +279,29 @@ fn random_noise(sprite: &mut SpriteGen) -> NoiseMap {
     map.noise
 }
 
+// fill the texture from the sprite's seed, the same seed and colors always give the same image
 pub fn noise_fill(sprite: &mut SpriteGen) {
-    let mut rng = rand::thread_rng();
+    let mut rng = stream_rng(sprite.seed(), Stream::Noise);
 
-    let map = random_noise(sprite);
+    let map = random_noise(&mut rng, sprite);
 
     let noise_plateau = noise_plateau(&mut rng, &sprite.char_color);
+    let mut fill_noise = Vec::with_capacity(sprite.char_texture.pixels.len());
     for index in 0..sprite.char_texture.pixels.len() {
         let (x, y) = sprite.char_texture.xy_from_index(index);
-        let possible_letters = get_noise_plateau_level(&noise_plateau, map.get_value(x, y));
+        let noise = map.get_value(x, y);
+        let possible_letters = get_noise_plateau_level(&noise_plateau, noise);
         sprite
             .char_texture
             .set(x, y, *possible_letters.choose(&mut rng).unwrap());
+        fill_noise.push(noise as f32);
     }
+    sprite.set_fill_noise(fill_noise);
+    sprite.reset_steps();
 }
 
 fn noise_plateau(
-    rng: &mut ThreadRng,
+    rng: &mut SeededRng,
     letter_colors: &BTreeMap<char, [u8; 4]>,
 ) -> Vec<(f64, Vec<char>)> {
     let letters: Vec<char> = letter_colors.keys().map(|s| s.to_owned()).collect();
@@ -329,3 +357,72 @@ fn get_noise_plateau_level(levels: &[(f64, Vec<char>)], noise: f64) -> &[char] {
     }
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // mean difference between two columns, or two rows when transposed
+    fn line_diff(map: &NoiseMap, transposed: bool, a: usize, b: usize) -> f64 {
+        let (width, height) = map.size();
+        let len = if transposed { width } else { height };
+        let value = |line, i| match transposed {
+            true => map.get_value(i, line),
+            false => map.get_value(line, i),
+        };
+        (0..len)
+            .map(|i| (value(a, i) - value(b, i)).abs())
+            .sum::<f64>()
+            / len as f64
+    }
+
+    #[test]
+    fn wrapped_noise_has_no_seam() {
+        for seed in 0..200 {
+            let mut sprite_gen = SpriteGen::new(32, 32);
+            sprite_gen.char_texture.boundary = Boundary::Wrap;
+            let map = random_noise(&mut stream_rng(seed, Stream::Noise), &sprite_gen);
+            for transposed in [false, true] {
+                let lines = if transposed {
+                    map.size().1
+                } else {
+                    map.size().0
+                };
+                /*
+                the last line next to the first is compared with the steps from the first to
+                the second and the second last to the last line. noise-rs blends less at the
+                edges, so those are the steps the seam should look like
+                */
+                let edge = line_diff(&map, transposed, lines - 1, 0);
+                let beside = line_diff(&map, transposed, 0, 1).max(line_diff(
+                    &map,
+                    transposed,
+                    lines - 2,
+                    lines - 1,
+                ));
+                assert!(
+                    edge.is_nan() || edge <= 2.0 * beside + 1e-9,
+                    "seed {}: edge {} beside {}",
+                    seed,
+                    edge,
+                    beside
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn unwrapped_noise_keeps_the_original_mapping() {
+        let bounds = (-3.0, 3.0);
+        for (value, index) in [(-3.0, 0), (-0.1, 0), (0.0, 0), (0.75, 2), (2.99, 7)] {
+            assert_eq!(GeneratedNoise::index(value, bounds, 16, false), index);
+        }
+        // wrapped, one extent past the bounds is the same cell
+        for value in [-3.0, -0.1, 0.75, 2.99] {
+            assert_eq!(
+                GeneratedNoise::index(value, bounds, 16, true),
+                GeneratedNoise::index(value + 6.0, bounds, 16, true)
+            );
+        }
+    }
+}
@@ -1,19 +1,41 @@
-use std::sync::{Arc, Mutex};
-
-use crate::{
-    rule::Rule,
-    save_and_load::{deserialize_config, serialize_config, texture_to_png_base64},
-    sprite_gen::SpriteGen,
-    texture_display::MainTexture,
+use crate::clipboard::Clipboard;
+use crate::config_files::{ConfigFiles, RecentConfig};
+use crate::preset_panel::PresetPanel;
+use crate::texture_display::MainTexture;
+use auto::{
+    animation::{AnimationFormat, AnimationPalette, AnimationRecorder, AnimationSettings},
+    char_texture::{Boundary, CharTexture},
+    history::History,
+    letter_grid::{grid_to_indexed_png, grid_to_text, letter_masks, mask_suffix},
+    neighborhood::Neighborhood,
+    palette::{write_palette, PaletteFormat, PaletteOrder},
+    presets::THUMBNAIL_SIZE,
+    random_rules::{Harmony, Transparency},
+    render::RenderMode,
+    rule::{Rule, RuleError},
+    save_and_load::{deserialize_config, serialize_config, texture_to_png, Config},
+    sprite_gen::{ApplyMode, ConflictPolicy, SpriteGen},
     texture_noise::noise_fill,
 };
-use bevy::{input::mouse::MouseWheel, prelude::*};
+use bevy::{input::mouse::MouseWheel, prelude::*, window::FileDragAndDrop};
 use bevy_egui::{
-    egui::{self, color::Hsva, Align2, Checkbox, ScrollArea, Slider},
+    egui::{
+        self,
+        color::Hsva,
+        color_picker::{color_edit_button_hsva, Alpha},
+        Align2, Checkbox, ScrollArea, Slider,
+    },
     EguiContext,
 };
 use wasm_bindgen::JsCast;
-use wasm_bindgen_futures::spawn_local;
+
+#[cfg(not(target_family = "wasm"))]
+use auto::{letter_grid::load_grid_file, palette::load_palette_file};
+#[cfg(target_family = "wasm")]
+use {
+    crate::{config_files::pick_file, pending::Pending},
+    auto::{letter_grid::grid_from_bytes, palette::read_palette},
+};
 
 pub struct UiContext {
     pub paint_letter: char,
@@ -22,14 +44,47 @@ pub struct UiContext {
     pub last_paint_point: Option<Vec2>,
 
     pub run_hold_toggle: bool,
+    pub tile_preview: bool,
 
     pub texture_dimensions: (usize, usize),
     pub update_texture_dimensions: bool,
 
     pub saved_image: String,
+    pub output_directory: String,
+    pub save_error: Option<String>,
     pub config_export: String,
-    pub config_import_delayed: bool,
     pub config_import: String,
+    pub config_error: Option<String>,
+    pub clipboard: Clipboard,
+    pub config_files: ConfigFiles,
+    pub config_name: String,
+    pub config_author: String,
+    pub presets: PresetPanel,
+    pub grid_path: String,
+    pub grid_error: Option<String>,
+    #[cfg(target_family = "wasm")]
+    pub grid_picked: Option<Pending<Result<(String, Vec<u8>), String>>>,
+    pub preset_steps_remaining: usize,
+
+    pub seed_input: String,
+    pub displayed_seed: Option<u64>,
+
+    pub custom_neighborhood: String,
+
+    pub color_harmony: Harmony,
+    pub color_transparency: Transparency,
+    pub palette_path: String,
+    pub palette_order: PaletteOrder,
+    pub palette_error: Option<String>,
+    #[cfg(target_family = "wasm")]
+    pub palette_picked: Option<Pending<Result<(String, Vec<u8>), String>>>,
+
+    pub until_stable_steps: usize,
+    pub until_stable_remaining: usize,
+
+    pub animation_settings: AnimationSettings,
+    pub animation: Option<AnimationRecorder>,
+    pub animation_error: Option<String>,
 }
 
 impl UiContext {
@@ -42,11 +97,44 @@ impl UiContext {
             texture_dimensions: (0, 0),
             update_texture_dimensions: false,
             run_hold_toggle: true,
+            tile_preview: false,
 
             saved_image: "".into(),
+            output_directory: "exports".into(),
+            save_error: None,
             config_export: "".into(),
-            config_import_delayed: false,
             config_import: "".into(),
+            config_error: None,
+            clipboard: Clipboard::default(),
+            config_files: ConfigFiles::load(),
+            config_name: "".into(),
+            config_author: "".into(),
+            presets: PresetPanel::load(),
+            grid_path: "grid.txt".into(),
+            grid_error: None,
+            #[cfg(target_family = "wasm")]
+            grid_picked: None,
+            preset_steps_remaining: 0,
+
+            seed_input: "".into(),
+            displayed_seed: None,
+
+            custom_neighborhood: "-1,0 0,0 1,0".into(),
+
+            color_harmony: Harmony::default(),
+            color_transparency: Transparency::default(),
+            palette_path: "palette.gpl".into(),
+            palette_order: PaletteOrder::InOrder,
+            palette_error: None,
+            #[cfg(target_family = "wasm")]
+            palette_picked: None,
+
+            until_stable_steps: 1000,
+            until_stable_remaining: 0,
+
+            animation_settings: AnimationSettings::default(),
+            animation: None,
+            animation_error: None,
         }
     }
 }
@@ -109,7 +197,9 @@ pub fn keybinds(
     }
 
     if keyboard_input.just_pressed(KeyCode::C) {
-        main_texture.sprite_gen.randomize_color();
+        main_texture
+            .sprite_gen
+            .randomize_color_with(ui_context.color_harmony, ui_context.color_transparency);
         main_texture.sprite_gen.set_changed();
     }
 
@@ -122,17 +212,59 @@ pub fn keybinds(
     }
 
     if keyboard_input.just_pressed(KeyCode::I) {
-        noise_fill(&mut main_texture.sprite_gen);
+        main_texture.sprite_gen.randomize_image();
+    }
+}
+
+/* history
+records what changed every frame, edits made while dragging, typing or painting are merged
+until they are done. undo and redo are handled here and not in keybinds so they also work
+with the pointer over a panel
+*/
+pub fn history(
+    mut egui_ctx: ResMut<EguiContext>,
+    mut ui_context: ResMut<UiContext>,
+    keyboard_input: Res<Input<KeyCode>>,
+    mut main_texture: ResMut<MainTexture>,
+    mut history: ResMut<History>,
+) {
+    let typing = egui_ctx.ctx_mut().wants_keyboard_input();
+    let dragging = egui_ctx.ctx_mut().is_using_pointer();
+    let sprite_gen = &mut main_texture.sprite_gen;
+
+    let control =
+        keyboard_input.pressed(KeyCode::LControl) || keyboard_input.pressed(KeyCode::RControl);
+    let shift = keyboard_input.pressed(KeyCode::LShift) || keyboard_input.pressed(KeyCode::RShift);
+    if control && !typing {
+        let moved = if keyboard_input.just_pressed(KeyCode::Z) && !shift {
+            history.undo(sprite_gen)
+        } else if keyboard_input.just_pressed(KeyCode::Y)
+            || (keyboard_input.just_pressed(KeyCode::Z) && shift)
+        {
+            history.redo(sprite_gen)
+        } else {
+            false
+        };
+        if moved {
+            restored(&mut ui_context, sprite_gen);
+            return;
+        }
     }
+
+    history.record(
+        sprite_gen,
+        typing || dragging || ui_context.currently_painting,
+    );
 }
 
 pub fn egui(
     mut egui_ctx: ResMut<EguiContext>,
     mut ui_context: ResMut<UiContext>,
     mut main_texture: ResMut<MainTexture>,
+    mut history: ResMut<History>,
 ) {
     let sprite_gen = &mut main_texture.sprite_gen;
-    egui::Window::new("Rules [Conidition, Action]")
+    egui::Window::new("Rules [Condition, Action, Neighborhood]")
         .anchor(Align2::RIGHT_TOP, [0., 0.])
         .min_width(200.)
         .show(egui_ctx.ctx_mut(), |ui| {
@@ -145,20 +277,89 @@ pub fn egui(
                 }
             });
 
-            ui.columns(2, |ui| {
-                for rule in sprite_gen.rules.iter_mut() {
+            egui::ComboBox::from_label("Update")
+                .selected_text(apply_mode_name(sprite_gen.apply_mode))
+                .show_ui(ui, |ui| {
+                    for mode in [
+                        ApplyMode::Sequential,
+                        ApplyMode::Synchronous(ConflictPolicy::LastWins),
+                        ApplyMode::Synchronous(ConflictPolicy::Priority),
+                        ApplyMode::Synchronous(ConflictPolicy::Random),
+                    ] {
+                        ui.selectable_value(
+                            &mut sprite_gen.apply_mode,
+                            mode,
+                            apply_mode_name(mode),
+                        );
+                    }
+                });
+
+            egui::Grid::new("rules").num_columns(3).show(ui, |ui| {
+                for (index, rule) in sprite_gen.rules.iter_mut().enumerate() {
                     let mut current_condition = rule.original_condition().to_owned();
-                    ui[0].text_edit_singleline(&mut current_condition);
+                    ui.add(egui::TextEdit::singleline(&mut current_condition).desired_width(100.));
                     if current_condition != *rule.original_condition() {
                         rule.set_condition(&current_condition);
                     }
-                }
-                for rule in sprite_gen.rules.iter_mut() {
+
                     let mut current_action = rule.original_action().to_owned();
-                    ui[1].text_edit_singleline(&mut current_action);
+                    ui.add(egui::TextEdit::singleline(&mut current_action).desired_width(100.));
                     if current_action != *rule.original_action() {
                         rule.set_action(&current_action);
                     }
+
+                    let mut neighborhood = rule.neighborhood().clone();
+                    egui::ComboBox::from_id_source(("neighborhood", index))
+                        .selected_text(neighborhood_name(&neighborhood))
+                        .show_ui(ui, |ui| {
+                            for preset in [
+                                Neighborhood::Moore(1),
+                                Neighborhood::Moore(2),
+                                Neighborhood::Moore(3),
+                                Neighborhood::VonNeumann(1),
+                                Neighborhood::VonNeumann(2),
+                                Neighborhood::Hexagonal,
+                            ] {
+                                let name = neighborhood_name(&preset);
+                                ui.selectable_value(&mut neighborhood, preset, name);
+                            }
+                            ui.separator();
+                            ui.label("Custom offsets \"x,y x,y ...\"");
+                            ui.horizontal(|ui| {
+                                if ui.button("Set").clicked() {
+                                    if let Some(custom) =
+                                        Neighborhood::parse_custom(&ui_context.custom_neighborhood)
+                                    {
+                                        neighborhood = custom;
+                                    }
+                                }
+                                ui.text_edit_singleline(&mut ui_context.custom_neighborhood);
+                            });
+                        });
+                    if neighborhood != *rule.neighborhood() {
+                        rule.set_neighborhood(neighborhood);
+                    }
+                    ui.end_row();
+
+                    // the rule is skipped until it parses, the error goes under the field at fault
+                    if let Some(error) = rule.error() {
+                        match error {
+                            RuleError::Condition(_) => {}
+                            RuleError::Action { .. } => {
+                                ui.label("");
+                            }
+                            RuleError::Neighborhood(_) => {
+                                ui.label("");
+                                ui.label("");
+                            }
+                        }
+                        ui.label(
+                            egui::RichText::new(error.to_string())
+                                .color(egui::Color32::RED)
+                                .monospace(),
+                        );
+                        ui.end_row();
+                    }
                 }
             });
         });
@@ -172,74 +373,568 @@ pub fn egui(
                     let height = sprite_gen.char_texture.dimensions.1;
                     let mut data = vec![255u8; width * height * 4];
                     sprite_gen.update_texture(&mut data);
-                    ui_context.saved_image = texture_to_png_base64(data, width, height);
-
-                    wasm_save_image(&ui_context.saved_image);
+                    let config = current_config(sprite_gen, &ui_context);
+                    match texture_to_png(&data, width, height, &config) {
+                        Ok(png) => save_export(&mut ui_context, &png, "png", "image/png", &config),
+                        Err(e) => {
+                            ui_context.save_error = Some(format!("could not encode png: {}", e))
+                        }
+                    }
                 }
+                #[cfg(target_family = "wasm")]
                 ui.text_edit_singleline(&mut ui_context.saved_image);
+                #[cfg(not(target_family = "wasm"))]
+                ui.text_edit_singleline(&mut ui_context.output_directory);
+            });
+            #[cfg(not(target_family = "wasm"))]
+            if !ui_context.saved_image.is_empty() {
+                ui.label(format!("Saved {}", ui_context.saved_image));
+            }
+            if let Some(error) = &ui_context.save_error {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+
+            /* animation
+            the recorder steps once per frame so the recording can be watched,
+            when it is done the file is saved like Save Image
+            */
+            if let Some(recorder) = ui_context.animation.as_mut() {
+                recorder.advance(sprite_gen);
+                if recorder.is_done() {
+                    let format = recorder.settings.format;
+                    match recorder.encode() {
+                        Ok(data) => {
+                            ui_context.animation_error = None;
+                            let config = current_config(sprite_gen, &ui_context);
+                            save_export(
+                                &mut ui_context,
+                                &data,
+                                format.extension(),
+                                format.mime(),
+                                &config,
+                            );
+                        }
+                        Err(e) => ui_context.animation_error = Some(e.to_string()),
+                    }
+                    ui_context.animation = None;
+                }
+            }
+            ui.collapsing("Animation", |ui| {
+                let settings = &mut ui_context.animation_settings;
+                egui::Grid::new("animation").num_columns(2).show(ui, |ui| {
+                    ui.label("Steps");
+                    ui.add(egui::DragValue::new(&mut settings.steps).clamp_range(1..=100_000));
+                    ui.end_row();
+                    ui.label("Frame every");
+                    ui.add(egui::DragValue::new(&mut settings.every).clamp_range(1..=1000));
+                    ui.end_row();
+                    ui.label("Delay (ms)");
+                    ui.add(
+                        egui::DragValue::new(&mut settings.frame_delay_ms).clamp_range(10..=10_000),
+                    );
+                    ui.end_row();
+                    ui.label("Format");
+                    egui::ComboBox::from_id_source("animation format")
+                        .selected_text(format!("{:?}", settings.format))
+                        .show_ui(ui, |ui| {
+                            for format in [AnimationFormat::Gif, AnimationFormat::Apng] {
+                                ui.selectable_value(
+                                    &mut settings.format,
+                                    format,
+                                    format!("{:?}", format),
+                                );
+                            }
+                        });
+                    ui.end_row();
+                    ui.label("Palette");
+                    ui.horizontal(|ui| {
+                        egui::ComboBox::from_id_source("animation palette")
+                            .selected_text(animation_palette_name(settings.palette))
+                            .show_ui(ui, |ui| {
+                                for palette in [
+                                    AnimationPalette::Letters,
+                                    AnimationPalette::Reduced(4),
+                                    AnimationPalette::Grayscale,
+                                ] {
+                                    let selected = std::mem::discriminant(&settings.palette)
+                                        == std::mem::discriminant(&palette);
+                                    if ui
+                                        .selectable_label(selected, animation_palette_name(palette))
+                                        .clicked()
+                                        && !selected
+                                    {
+                                        settings.palette = palette;
+                                    }
+                                }
+                            });
+                        if let AnimationPalette::Reduced(colors) = &mut settings.palette {
+                            ui.add(egui::DragValue::new(colors).clamp_range(1..=27));
+                        }
+                    });
+                    ui.end_row();
+                });
+                ui.checkbox(&mut settings.from_noise, "Start from new noise");
+
+                match &ui_context.animation {
+                    Some(recorder) => {
+                        let progress = format!(
+                            "recording, step {}/{}",
+                            recorder.steps_done(),
+                            recorder.settings.steps
+                        );
+                        if ui.button("Cancel").clicked() {
+                            ui_context.animation = None;
+                        }
+                        ui.label(progress);
+                    }
+                    None => {
+                        if ui.button("Record Animation").clicked() {
+                            ui_context.until_stable_remaining = 0;
+                            ui_context.preset_steps_remaining = 0;
+                            let settings = ui_context.animation_settings.clone();
+                            match AnimationRecorder::new(sprite_gen, settings) {
+                                Ok(recorder) => {
+                                    ui_context.animation = Some(recorder);
+                                    ui_context.animation_error = None;
+                                }
+                                Err(e) => ui_context.animation_error = Some(e.to_string()),
+                            }
+                        }
+                    }
+                }
+                if let Some(error) = &ui_context.animation_error {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
             });
 
             ui.separator();
 
+            ui.horizontal(|ui| {
+                ui.label("Name");
+                ui.text_edit_singleline(&mut ui_context.config_name);
+            });
+            ui.horizontal(|ui| {
+                ui.label("Author");
+                ui.text_edit_singleline(&mut ui_context.config_author);
+            });
+
             ui.horizontal(|ui| {
                 if ui.button("Export Config").clicked() {
-                    ui_context.config_export =
-                        serialize_config(&sprite_gen.rules, &sprite_gen.char_color);
-                    set_clipboard(&ui_context.config_export);
+                    let config = current_config(sprite_gen, &ui_context);
+                    ui_context.config_export = serialize_config(&config);
+                    ui_context.config_error =
+                        ui_context.clipboard.set(&ui_context.config_export).err();
                 }
                 ui.text_edit_singleline(&mut ui_context.config_export);
             });
 
             ui.horizontal(|ui| {
                 /* load config
-                if not wasm, load from text field
-                if wasm, load from clipboard once we can lock clipboard mutex
+                the clipboard is read into the text field and the field is loaded, if the
+                clipboard can not be read the field is loaded as it is
                 */
-                lazy_static::lazy_static! {
-                    static ref CLIPBOARD_BUFFER: Arc<Mutex<(bool, String)>> = Arc::new(Mutex::new((false, String::new())));
-                }
                 let mut config_load_ready = false;
-                #[cfg(target_family = "wasm")] {
-                    if ui_context.config_import_delayed {
-                        if let Ok(mut clipboard) = CLIPBOARD_BUFFER.try_lock() {
-                            if clipboard.0 {
-                                ui_context.config_import = clipboard.1.clone();
-                                *clipboard = (false,"".into());
-                                ui_context.config_import_delayed = false;
-                                config_load_ready = true;
-                            }
+                if let Some(result) = ui_context.clipboard.poll() {
+                    match result {
+                        Ok(text) => ui_context.config_import = text,
+                        Err(e) if ui_context.config_import.trim().is_empty() => {
+                            ui_context.config_error = Some(e)
                         }
+                        Err(_) => {}
                     }
+                    config_load_ready = !ui_context.config_import.trim().is_empty();
                 }
 
                 if ui.button("Import Config").clicked() {
-                    config_load_ready = true;
-                    #[cfg(target_family = "wasm")] {
-                        ui_context.config_import_delayed = true;
-                        get_clipboard(CLIPBOARD_BUFFER.clone());
-                        config_load_ready = false;
-                    }
+                    ui_context.clipboard.request();
+                }
+                if ui_context.clipboard.is_pending() {
+                    ui.label("reading clipboard");
                 }
 
                 if config_load_ready {
-                    if let Some((rules, colors)) = deserialize_config(&ui_context.config_import) {
-                        sprite_gen.rules = rules;
-                        sprite_gen.char_color = colors;
+                    match deserialize_config(&ui_context.config_import) {
+                        Ok(config) => load_config(&mut ui_context, sprite_gen, config),
+                        Err(e) => ui_context.config_error = Some(e.to_string()),
                     }
                 }
                 ui.text_edit_singleline(&mut ui_context.config_import);
             });
 
+            /* config files
+            desktop builds open and save the path in the text field,
+            the browser opens a file picker and downloads the saved config
+            */
+            if let Some(result) = ui_context.config_files.poll_open() {
+                match result {
+                    Ok(config) => load_config(&mut ui_context, sprite_gen, config),
+                    Err(e) => ui_context.config_error = Some(e),
+                }
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Open Config").clicked() {
+                    match ui_context.config_files.open() {
+                        Ok(Some(config)) => load_config(&mut ui_context, sprite_gen, config),
+                        Ok(None) => {}
+                        Err(e) => ui_context.config_error = Some(e),
+                    }
+                }
+                if ui.button("Save Config").clicked() {
+                    let config = current_config(sprite_gen, &ui_context);
+                    ui_context.config_error = ui_context.config_files.save(&config).err();
+                }
+                #[cfg(not(target_family = "wasm"))]
+                ui.text_edit_singleline(&mut ui_context.config_files.path);
+            });
+            if ui.button("Presets").clicked() {
+                ui_context.presets.open = !ui_context.presets.open;
+            }
+            let mut opened = None;
+            ui.collapsing("Recent Configs", |ui| {
+                if ui_context.config_files.recent.is_empty() {
+                    ui.label("none yet");
+                }
+                for recent in ui_context.config_files.recent.iter() {
+                    if ui.button(recent.label()).clicked() {
+                        opened = Some(recent.clone());
+                    }
+                }
+            });
+            if let Some(recent) = opened {
+                match ui_context.config_files.open_recent(&recent) {
+                    Ok(config) => load_config(&mut ui_context, sprite_gen, config),
+                    Err(e) => ui_context.config_error = Some(e),
+                }
+            }
+            if let Some(error) = &ui_context.config_error {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+
+            /* letter grid
+            the letters instead of their colors, saved like Save Image. an imported grid
+            replaces the letters and keeps the rules, desktop builds import the text or
+            indexed png at the path, the browser opens a file picker
+            */
+            #[cfg(target_family = "wasm")]
+            if let Some(result) = ui_context.grid_picked.as_ref().and_then(Pending::take) {
+                ui_context.grid_picked = None;
+                match result.and_then(|(name, data)| {
+                    grid_from_bytes(&data).map_err(|e| format!("{}: {}", name, e))
+                }) {
+                    Ok(grid) => imported_grid(&mut ui_context, sprite_gen, &grid),
+                    Err(e) => ui_context.grid_error = Some(e),
+                }
+            }
+            ui.collapsing("Letter Grid", |ui| {
+                ui.horizontal(|ui| {
+                    let texture = &sprite_gen.char_texture;
+                    if ui.button("Text").clicked() {
+                        let config = current_config(sprite_gen, &ui_context);
+                        let text = grid_to_text(texture);
+                        save_export(&mut ui_context, text.as_bytes(), "txt", "text/plain", &config);
+                    }
+                    if ui.button("Indexed PNG").clicked() {
+                        let config = current_config(sprite_gen, &ui_context);
+                        match grid_to_indexed_png(texture, &sprite_gen.char_color, &config) {
+                            Ok(png) => {
+                                save_export(&mut ui_context, &png, "png", "image/png", &config)
+                            }
+                            Err(e) => ui_context.grid_error = Some(e.to_string()),
+                        }
+                    }
+                    if ui.button("Masks").clicked() {
+                        let config = current_config(sprite_gen, &ui_context);
+                        match letter_masks(texture) {
+                            Ok(masks) => {
+                                let files: Vec<(String, &[u8])> = masks
+                                    .iter()
+                                    .map(|(letter, png)| {
+                                        (format!("-{}.png", mask_suffix(*letter)), png.as_slice())
+                                    })
+                                    .collect();
+                                save_export_files(&mut ui_context, &files, "image/png", &config);
+                            }
+                            Err(e) => ui_context.grid_error = Some(e.to_string()),
+                        }
+                    }
+                });
+                ui.horizontal(|ui| {
+                    if ui.button("Import Grid").clicked() {
+                        #[cfg(target_family = "wasm")]
+                        {
+                            let picked = Pending::new();
+                            let accept = ".txt,.png,text/plain,image/png";
+                            match pick_file(accept, picked.clone()) {
+                                Ok(()) => ui_context.grid_picked = Some(picked),
+                                Err(e) => ui_context.grid_error = Some(e),
+                            }
+                        }
+                        #[cfg(not(target_family = "wasm"))]
+                        {
+                            let path = std::path::PathBuf::from(ui_context.grid_path.trim());
+                            match load_grid_file(&path) {
+                                Ok(grid) => imported_grid(&mut ui_context, sprite_gen, &grid),
+                                Err(e) => {
+                                    ui_context.grid_error =
+                                        Some(format!("{}: {}", path.display(), e))
+                                }
+                            }
+                        }
+                    }
+                    #[cfg(not(target_family = "wasm"))]
+                    ui.text_edit_singleline(&mut ui_context.grid_path);
+                });
+                if let Some(error) = &ui_context.grid_error {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+            });
+
+            ui.separator();
+
+            /* seed
+            show the current seed unless the user is typing a new one,
+            applying a seed restarts the image with the current rules
+            */
+            if ui_context.displayed_seed != Some(sprite_gen.seed()) {
+                ui_context.displayed_seed = Some(sprite_gen.seed());
+                ui_context.seed_input = sprite_gen.seed().to_string();
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Seed").clicked() {
+                    if let Ok(seed) = ui_context.seed_input.trim().parse() {
+                        sprite_gen.set_seed(seed);
+                        noise_fill(sprite_gen);
+                    }
+                }
+                ui.text_edit_singleline(&mut ui_context.seed_input);
+            });
+            ui.label(format!("Steps: {}", sprite_gen.steps()));
+
+            /* convergence
+            run until stable steps once per frame so the texture can be
+            watched, it stops early once a fixed point or cycle is found
+            */
+            if ui_context.preset_steps_remaining > 0 {
+                apply_rules(sprite_gen);
+                ui_context.preset_steps_remaining -= 1;
+            } else if ui_context.until_stable_remaining > 0 {
+                if sprite_gen.convergence.result().is_some() {
+                    ui_context.until_stable_remaining = 0;
+                } else {
+                    apply_rules(sprite_gen);
+                    ui_context.until_stable_remaining -= 1;
+                }
+            }
+            match sprite_gen.convergence.result() {
+                _ if ui_context.preset_steps_remaining > 0 => ui.label(format!(
+                    "running preset, {} steps left",
+                    ui_context.preset_steps_remaining
+                )),
+                Some(convergence) => ui.label(convergence.to_string()),
+                None if ui_context.until_stable_remaining > 0 => ui.label(format!(
+                    "running, {} steps left",
+                    ui_context.until_stable_remaining
+                )),
+                None => ui.label("changing"),
+            };
+            ui.horizontal(|ui| {
+                if ui.button("Run until stable").clicked() {
+                    ui_context.until_stable_remaining = ui_context.until_stable_steps;
+                }
+                ui.label("max");
+                ui.add(
+                    egui::DragValue::new(&mut ui_context.until_stable_steps)
+                        .clamp_range(1..=100_000),
+                );
+            });
+
+            ui.separator();
+
+            /* timeline
+            every entry of the history, going back and editing or stepping
+            drops the entries after it and continues from there
+            */
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(history.can_undo(), egui::Button::new("Undo (Ctrl+Z)"))
+                    .clicked()
+                    && history.undo(sprite_gen)
+                {
+                    restored(&mut ui_context, sprite_gen);
+                }
+                if ui
+                    .add_enabled(history.can_redo(), egui::Button::new("Redo (Ctrl+Y)"))
+                    .clicked()
+                    && history.redo(sprite_gen)
+                {
+                    restored(&mut ui_context, sprite_gen);
+                }
+            });
+            let mut index = history.cursor();
+            ui.add(Slider::new(&mut index, 0..=history.len().saturating_sub(1)).show_value(false));
+            if index != history.cursor() && history.go_to(index, sprite_gen) {
+                restored(&mut ui_context, sprite_gen);
+            }
+            if let Some((edit, steps)) = history.edit(history.cursor()) {
+                ui.label(format!(
+                    "{}/{}: {}, step {}",
+                    history.cursor() + 1,
+                    history.len(),
+                    edit,
+                    steps
+                ));
+            }
+
             ui.separator();
 
             if ui.button("Randomize All (R)").clicked() {
                 sprite_gen.randomize();
             }
-            if ui.button("Randomize Colors (C)").clicked() {
-                sprite_gen.randomize_color();
+            let unlocked = sprite_gen.locked_palette.is_none();
+            if ui
+                .add_enabled(unlocked, egui::Button::new("Randomize Colors (C)"))
+                .clicked()
+            {
+                sprite_gen
+                    .randomize_color_with(ui_context.color_harmony, ui_context.color_transparency);
+            }
+            ui.collapsing("Color Settings", |ui| {
+                // how the colors are picked, a new choice starts from its defaults
+                let harmony = &mut ui_context.color_harmony;
+                egui::ComboBox::from_label("Harmony")
+                    .selected_text(harmony.name())
+                    .show_ui(ui, |ui| {
+                        for option in [
+                            Harmony::Random,
+                            Harmony::Complementary,
+                            Harmony::Triadic,
+                            Harmony::Analogous { spread: 60.0 },
+                            Harmony::Monochrome,
+                            Harmony::Gradient {
+                                from: [32, 16, 48, 255],
+                                to: [240, 224, 160, 255],
+                            },
+                            Harmony::OkLabRamp,
+                            Harmony::OkLchRamp,
+                        ] {
+                            let selected = harmony.name() == option.name();
+                            if ui.selectable_label(selected, option.name()).clicked() && !selected {
+                                *harmony = option;
+                            }
+                        }
+                    });
+                match harmony {
+                    Harmony::Analogous { spread } => {
+                        ui.horizontal(|ui| {
+                            ui.label("Spread");
+                            ui.add(Slider::new(spread, 0.0..=180.0));
+                        });
+                    }
+                    Harmony::Gradient { from, to } => {
+                        ui.horizontal(|ui| {
+                            ui.label("From");
+                            edit_color(ui, from);
+                            ui.label("to");
+                            edit_color(ui, to);
+                        });
+                    }
+                    _ => {}
+                }
+
+                // chance of a see-through letter and the alpha it gets
+                let transparency = &mut ui_context.color_transparency;
+                ui.horizontal(|ui| {
+                    ui.label("Transparent");
+                    ui.add(Slider::new(&mut transparency.chance, 0.0..=1.0));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Alpha");
+                    ui.add(egui::DragValue::new(&mut transparency.alpha_range.0));
+                    ui.label("to");
+                    ui.add(egui::DragValue::new(&mut transparency.alpha_range.1));
+                });
+            });
+
+            /* palette
+            GIMP, Paint.NET, Adobe and Lospec palettes onto the letters and the letter colors
+            out in the same formats. desktop builds import the palette at the path, the
+            browser opens a file picker. a locked palette is kept by Randomize All and
+            Randomize Colors, imported palettes replace it
+            */
+            #[cfg(target_family = "wasm")]
+            if let Some(result) = ui_context.palette_picked.as_ref().and_then(Pending::take) {
+                ui_context.palette_picked = None;
+                match result.and_then(|(name, data)| {
+                    read_palette(&data).map_err(|e| format!("{}: {}", name, e))
+                }) {
+                    Ok(colors) => imported_palette(&mut ui_context, sprite_gen, &colors),
+                    Err(e) => ui_context.palette_error = Some(e),
+                }
             }
+            ui.collapsing("Palette", |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button("Import Palette").clicked() {
+                        #[cfg(target_family = "wasm")]
+                        {
+                            let picked = Pending::new();
+                            match pick_file(".gpl,.txt,.ase,.hex", picked.clone()) {
+                                Ok(()) => ui_context.palette_picked = Some(picked),
+                                Err(e) => ui_context.palette_error = Some(e),
+                            }
+                        }
+                        #[cfg(not(target_family = "wasm"))]
+                        {
+                            let path = std::path::PathBuf::from(ui_context.palette_path.trim());
+                            match load_palette_file(&path) {
+                                Ok(colors) => {
+                                    imported_palette(&mut ui_context, sprite_gen, &colors)
+                                }
+                                Err(e) => {
+                                    ui_context.palette_error =
+                                        Some(format!("{}: {}", path.display(), e))
+                                }
+                            }
+                        }
+                    }
+                    #[cfg(not(target_family = "wasm"))]
+                    ui.text_edit_singleline(&mut ui_context.palette_path);
+                });
+                ui.horizontal(|ui| {
+                    let order = &mut ui_context.palette_order;
+                    ui.radio_value(order, PaletteOrder::InOrder, "In order");
+                    ui.radio_value(order, PaletteOrder::ByLuminance, "By luminance");
+                });
+                ui.label("Export");
+                ui.horizontal_wrapped(|ui| {
+                    for format in [
+                        PaletteFormat::Gpl,
+                        PaletteFormat::PaintNet,
+                        PaletteFormat::Ase,
+                        PaletteFormat::Hex,
+                    ] {
+                        if ui.button(format.name()).clicked() {
+                            let config = current_config(sprite_gen, &ui_context);
+                            let palette =
+                                write_palette(&sprite_gen.char_color, format, &config.name);
+                            let (extension, mime) = (format.extension(), format.mime());
+                            save_export(&mut ui_context, &palette, extension, mime, &config);
+                        }
+                    }
+                });
+                let mut locked = !unlocked;
+                if ui.checkbox(&mut locked, "Lock palette").changed() {
+                    if locked {
+                        sprite_gen.lock_palette();
+                    } else {
+                        sprite_gen.locked_palette = None;
+                    }
+                }
+                if let Some(error) = &ui_context.palette_error {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+            });
             if ui.button("Randomize Image (I)").clicked() {
-                noise_fill(sprite_gen);
+                sprite_gen.randomize_image();
             }
             if ui.button("Randomize Rules (F)").clicked() {
                 sprite_gen.randomize_rules();
@@ -257,6 +952,63 @@ pub fn egui(
                 ));
             });
 
+            ui.horizontal(|ui| {
+                ui.add(Checkbox::new(&mut ui_context.tile_preview, "Tile Preview"));
+            });
+
+            egui::ComboBox::from_label("Edges")
+                .selected_text(format!("{:?}", sprite_gen.char_texture.boundary))
+                .show_ui(ui, |ui| {
+                    for boundary in [
+                        Boundary::Fill,
+                        Boundary::Wrap,
+                        Boundary::Mirror,
+                        Boundary::Clamp,
+                    ] {
+                        ui.selectable_value(
+                            &mut sprite_gen.char_texture.boundary,
+                            boundary,
+                            format!("{:?}", boundary),
+                        );
+                    }
+                });
+
+            // shading of the letter colors, a new choice keeps the depth
+            let mut render_mode = sprite_gen.render_mode;
+            let depth = match render_mode {
+                RenderMode::Flat => 0.5,
+                mode => mode.depth(),
+            };
+            egui::ComboBox::from_label("Render")
+                .selected_text(render_mode.name())
+                .show_ui(ui, |ui| {
+                    for option in [
+                        RenderMode::Flat,
+                        RenderMode::Neighbors { depth },
+                        RenderMode::Age { depth, steps: 20 },
+                        RenderMode::Noise { depth },
+                    ] {
+                        let selected = render_mode.name() == option.name();
+                        if ui.selectable_label(selected, option.name()).clicked() && !selected {
+                            render_mode = option;
+                        }
+                    }
+                });
+            match &mut render_mode {
+                RenderMode::Flat => {}
+                RenderMode::Neighbors { depth } | RenderMode::Noise { depth } => {
+                    ui.add(Slider::new(depth, 0.0..=1.0).text("Depth"));
+                }
+                RenderMode::Age { depth, steps } => {
+                    ui.add(Slider::new(depth, 0.0..=1.0).text("Depth"));
+                    ui.add(Slider::new(steps, 1..=200).text("Steps"));
+                }
+            }
+            if render_mode != sprite_gen.render_mode {
+                sprite_gen.render_mode = render_mode;
+                sprite_gen.set_changed();
+            }
+
             ui.separator();
 
             ui.horizontal(|ui| {
@@ -314,12 +1066,14 @@ pub fn egui(
                     let next_letter =
                         std::char::from_u32(last_letter as u32 + 1).unwrap_or(last_letter);
                     if next_letter.is_alphabetic() && last_letter != next_letter {
-                        sprite_gen.char_color.insert(next_letter, [0, 0, 0, 0]);
+                        sprite_gen.char_color.insert(next_letter, [0, 0, 0, 255]);
+                        sprite_gen.set_changed();
                     }
                 }
                 if ui.button("-").clicked() {
                     if let Some(c) = sprite_gen.char_color.iter().map(|x| x.0.to_owned()).last() {
                         sprite_gen.char_color.remove(&c);
+                        sprite_gen.set_changed();
                     }
                 }
             });
@@ -331,27 +1085,17 @@ pub fn egui(
                 .show(ui, |ui| {
                     ui.vertical(|ui| {
                         for (letter, color) in sprite_gen.char_color.iter_mut() {
-                            let mut current_color = Hsva::from_srgb([color[0], color[1], color[2]]);
                             ui.horizontal(|ui| {
                                 let letter_string = [*letter].iter().collect::<String>();
                                 ui.label(letter_string);
-                                ui.color_edit_button_hsva(&mut current_color);
+                                if edit_color(ui, color) {
+                                    colors_changed = true;
+                                }
                                 if ui.radio(ui_context.paint_letter == *letter, "").clicked() {
                                     ui_context.paint_letter = *letter;
                                 }
                                 ui.label(format!("{}", sprite_gen.char_texture.count(*letter)));
                             });
-
-                            let compare_color = current_color.to_srgb();
-                            if color[0] != compare_color[0]
-                                || color[1] != compare_color[1]
-                                || color[2] != compare_color[2]
-                            {
-                                color[0] = compare_color[0];
-                                color[1] = compare_color[1];
-                                color[2] = compare_color[2];
-                                colors_changed = true;
-                            }
                         }
 
                         // artificial padding
@@ -365,13 +1109,250 @@ pub fn egui(
                 sprite_gen.set_changed();
             }
         });
+
+    /* presets
+    clicking a thumbnail or Load takes over the preset and runs its steps, once per
+    frame like Run until stable. the current config is saved as a user preset under the
+    name in the field, or the config name
+    */
+    let mut open = ui_context.presets.open;
+    let mut loaded = None;
+    egui::Window::new("Presets")
+        .open(&mut open)
+        .default_width(260.)
+        .show(egui_ctx.ctx_mut(), |ui| {
+            let mut removed = None;
+            ScrollArea::vertical().max_height(400.).show(ui, |ui| {
+                egui::Grid::new("presets").num_columns(2).show(ui, |ui| {
+                    // thumbnails are rendered one at a time
+                    let mut start = true;
+                    let size = egui::vec2(THUMBNAIL_SIZE as f32, THUMBNAIL_SIZE as f32);
+                    for index in 0..ui_context.presets.library.presets.len() {
+                        match ui_context.presets.thumbnail(ui.ctx(), index, &mut start) {
+                            Some(texture) => {
+                                if ui.add(egui::ImageButton::new(texture, size)).clicked() {
+                                    loaded = Some(index);
+                                }
+                            }
+                            None => {
+                                ui.add_sized(size, egui::Label::new("..."));
+                            }
+                        }
+
+                        let preset = &ui_context.presets.library.presets[index];
+                        ui.vertical(|ui| {
+                            ui.label(preset.name());
+                            if !preset.config.author.is_empty() {
+                                ui.label(format!("by {}", preset.config.author));
+                            }
+                            ui.label(format!("{} steps", preset.config.steps));
+                            ui.horizontal(|ui| {
+                                if ui.button("Load").clicked() {
+                                    loaded = Some(index);
+                                }
+                                if !preset.builtin && ui.button("Remove").clicked() {
+                                    removed = Some(index);
+                                }
+                            });
+                        });
+                        ui.end_row();
+                    }
+                });
+            });
+            if let Some(index) = removed {
+                ui_context.config_error = ui_context.presets.remove(index).err();
+            }
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui.button("Save as Preset").clicked() {
+                    let mut config = current_config(sprite_gen, &ui_context);
+                    let name = ui_context.presets.name.trim();
+                    if !name.is_empty() {
+                        config.name = name.to_string();
+                    } else if config.name.is_empty() {
+                        let count = ui_context.presets.library.presets.len();
+                        config.name = format!("Preset {}", count + 1);
+                    }
+                    // presets start in whatever size the texture has
+                    config.dimensions = None;
+                    ui_context.config_error = ui_context.presets.add(config).err();
+                }
+                ui.text_edit_singleline(&mut ui_context.presets.name);
+            });
+        });
+    ui_context.presets.open = open;
+
+    if let Some(index) = loaded {
+        let config = ui_context.presets.library.presets[index].config.clone();
+        let steps = config.steps;
+        load_config(&mut ui_context, sprite_gen, config);
+        // the steps are meant for a fresh noise fill, also for presets without a seed
+        noise_fill(sprite_gen);
+        ui_context.until_stable_remaining = 0;
+        ui_context.preset_steps_remaining = steps;
+    }
+}
+
+// files dropped on the window are opened like Open Config, pngs saved by the app included
+pub fn file_drop(
+    mut events: EventReader<FileDragAndDrop>,
+    mut ui_context: ResMut<UiContext>,
+    mut main_texture: ResMut<MainTexture>,
+) {
+    for event in events.iter() {
+        if let FileDragAndDrop::DroppedFile { path_buf, .. } = event {
+            let recent = RecentConfig::File(path_buf.clone());
+            match ui_context.config_files.open_recent(&recent) {
+                Ok(config) => load_config(&mut ui_context, &mut main_texture.sprite_gen, config),
+                Err(e) => ui_context.config_error = Some(e),
+            }
+        }
+    }
+}
+
+// take over rules, colors and whatever else the config has, a new seed or size refills the
+// image
+fn load_config(ui_context: &mut UiContext, sprite_gen: &mut SpriteGen, config: Config) {
+    let resized = config
+        .dimensions
+        .filter(|d| (d.width, d.height) != sprite_gen.char_texture.dimensions)
+        .is_some();
+    config.load_into(sprite_gen);
+    if let Some(dimensions) = config.dimensions {
+        ui_context.texture_dimensions = (dimensions.width, dimensions.height);
+        ui_context.update_texture_dimensions = true;
+    }
+    if config.seed.is_some() || resized {
+        noise_fill(sprite_gen);
+    }
+    sprite_gen.set_changed();
+    ui_context.preset_steps_remaining = 0;
+    ui_context.config_name = config.name;
+    ui_context.config_author = config.author;
+    // the config opened, the recent list may still have failed to store
+    ui_context.config_error = ui_context.config_files.store_error.take();
+}
+
+fn current_config(sprite_gen: &SpriteGen, ui_context: &UiContext) -> Config {
+    let mut config = Config::from_sprite_gen(sprite_gen);
+    config.name = ui_context.config_name.clone();
+    config.author = ui_context.config_author.clone();
+    config
+}
+
+fn apply_mode_name(mode: ApplyMode) -> &'static str {
+    match mode {
+        ApplyMode::Sequential => "Sequential",
+        ApplyMode::Synchronous(ConflictPolicy::LastWins) => "Synchronous, last wins",
+        ApplyMode::Synchronous(ConflictPolicy::Priority) => "Synchronous, first rule wins",
+        ApplyMode::Synchronous(ConflictPolicy::Random) => "Synchronous, random winner",
+    }
+}
+
+fn animation_palette_name(palette: AnimationPalette) -> &'static str {
+    match palette {
+        AnimationPalette::Letters => "Letters",
+        AnimationPalette::Reduced(_) => "Reduced",
+        AnimationPalette::Grayscale => "Grayscale",
+    }
+}
+
+fn neighborhood_name(neighborhood: &Neighborhood) -> String {
+    match neighborhood {
+        Neighborhood::Moore(radius) => format!("{0}x{0}", radius * 2 + 1),
+        Neighborhood::VonNeumann(radius) => format!("Von Neumann {}", radius),
+        Neighborhood::Hexagonal => "Hexagonal".into(),
+        Neighborhood::Custom(offsets) => format!("Custom ({})", offsets.len()),
+    }
+}
+
+// the grid can have another size, resize_texture recreates the image then
+fn imported_grid(ui_context: &mut UiContext, sprite_gen: &mut SpriteGen, grid: &CharTexture) {
+    if let Err(e) = sprite_gen.set_letters(grid) {
+        ui_context.grid_error = Some(e.to_string());
+        return;
+    }
+    ui_context.texture_dimensions = grid.dimensions;
+    ui_context.update_texture_dimensions = true;
+    ui_context.until_stable_remaining = 0;
+    ui_context.preset_steps_remaining = 0;
+    ui_context.grid_error = None;
+}
+
+// rgba with alpha, true if it was changed
+fn edit_color(ui: &mut egui::Ui, color: &mut [u8; 4]) -> bool {
+    let mut edited = Hsva::from_srgba_unmultiplied(*color);
+    color_edit_button_hsva(ui, &mut edited, Alpha::OnlyBlend);
+    let edited = edited.to_srgba_unmultiplied();
+    let changed = *color != edited;
+    *color = edited;
+    changed
+}
+
+fn imported_palette(ui_context: &mut UiContext, sprite_gen: &mut SpriteGen, colors: &[[u8; 4]]) {
+    sprite_gen.set_palette(colors, ui_context.palette_order);
+    ui_context.palette_error = None;
+}
+
+// the restored entry can have another size, resize_texture recreates the image then
+fn restored(ui_context: &mut UiContext, sprite_gen: &SpriteGen) {
+    ui_context.texture_dimensions = sprite_gen.char_texture.dimensions;
+    ui_context.update_texture_dimensions = true;
+    ui_context.until_stable_remaining = 0;
+    ui_context.preset_steps_remaining = 0;
 }
 
 fn apply_rules(sprite_gen: &mut SpriteGen) {
     sprite_gen.apply();
 }
 
-fn wasm_save_image(data: &str) {
+fn save_export(
+    ui_context: &mut UiContext,
+    data: &[u8],
+    extension: &str,
+    mime: &str,
+    config: &Config,
+) {
+    save_export_files(ui_context, &[(format!(".{}", extension), data)], mime, config);
+}
+
+/* save
+the browser downloads the files from data urls, desktop builds write them to the output
+directory with a timestamped name and the config as json next to them. a file is named
+<name or texture>-<timestamp><suffix>, just texture<suffix> in the browser
+*/
+// each target only uses some of the arguments
+#[allow(unused_variables)]
+fn save_export_files(
+    ui_context: &mut UiContext,
+    files: &[(String, &[u8])],
+    mime: &str,
+    config: &Config,
+) {
+    #[cfg(target_family = "wasm")]
+    for (suffix, data) in files {
+        ui_context.saved_image = format!("data:{};base64,{}", mime, base64::encode(data));
+        wasm_download(&ui_context.saved_image, &format!("texture{}", suffix));
+    }
+    #[cfg(not(target_family = "wasm"))]
+    match auto::save_and_load::write_export_files(
+        std::path::Path::new(&ui_context.output_directory),
+        files,
+        config,
+    ) {
+        Ok(paths) => {
+            ui_context.saved_image = match paths.len() {
+                1 => paths[0].display().to_string(),
+                count => format!("{} and {} more", paths[0].display(), count - 1),
+            };
+            ui_context.save_error = None;
+        }
+        Err(e) => ui_context.save_error = Some(format!("could not save: {}", e)),
+    }
+}
+
+pub fn wasm_download(data: &str, file_name: &str) {
     #[cfg(target_family = "wasm")]
     {
         let browser_window = web_sys::window().expect("could not get window");
@@ -384,7 +1365,7 @@ fn wasm_save_image(data: &str) {
             .set_attribute("href", data)
             .expect("could not set element attribute");
         save_element
-            .set_attribute("download", "texture.png")
+            .set_attribute("download", file_name)
             .expect("could not set element attribute");
 
         save_element
@@ -393,50 +1374,3 @@ fn wasm_save_image(data: &str) {
             .click();
     }
 }
-
-fn set_clipboard(data: &str) {
-    #[cfg(target_family = "wasm")]
-    {
-        let clipboard = web_sys::window()
-            .expect("could not get window")
-            .navigator()
-            .clipboard()
-            .expect("could not get clipboard");
-
-        let data_clone = data.to_string();
-        spawn_local(async move {
-            let result =
-                wasm_bindgen_futures::JsFuture::from(clipboard.write_text(&data_clone)).await;
-            if result.is_ok() {
-                println!("test1");
-            } else {
-                println!("test2");
-            }
-        });
-    }
-}
-
-fn get_clipboard(clipboard_buffer: Arc<Mutex<(bool, String)>>) {
-    #[cfg(target_family = "wasm")]
-    {
-        let clipboard = web_sys::window()
-            .expect("could not get window")
-            .navigator()
-            .clipboard()
-            .expect("could not get clipboard");
-
-        spawn_local(async move {
-            let result = wasm_bindgen_futures::JsFuture::from(clipboard.read_text()).await;
-
-            if let Ok(mut clipboard) = clipboard_buffer.try_lock() {
-                *clipboard = (
-                    true,
-                    result
-                        .expect("could not get clipboard")
-                        .as_string()
-                        .expect("could not read clipboard"),
-                );
-            }
-        });
-    }
-}